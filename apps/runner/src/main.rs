@@ -1,4 +1,6 @@
-use runtime::{Language, Runtime, PythonRuntime};
+mod repl;
+
+use runtime::{DalRuntime, Language, Runtime, PythonRuntime};
 use pyo3::prepare_freethreaded_python;
 
 struct Runner {
@@ -15,13 +17,52 @@ impl Runner {
                     runtime: Box::new(PythonRuntime::new()),
                 }
             },
+            Language::Dal => Runner {
+                runtime: Box::new(DalRuntime::new()),
+            },
             _ => unimplemented!("Language not implemented")
         }
     }
 }
 
+fn usage() -> ! {
+    eprintln!("usage: runner [repl [--language dal|python]]");
+    std::process::exit(2);
+}
+
+fn repl(args: &[String]) {
+    let language = match args {
+        [] => "python",
+        [flag, language] if flag == "--language" => language.as_str(),
+        _ => usage(),
+    };
+
+    let language = match language {
+        "python" => Language::Python,
+        "dal" => {
+            eprintln!("runner: dal has no evaluator yet, so entries are read and printed back");
+            Language::Dal
+        }
+        _ => usage(),
+    };
+
+    let runner = Runner::new(language);
+
+    if let Err(e) = repl::run(runner.runtime.as_ref()) {
+        eprintln!("runner: {}", e);
+        std::process::exit(1);
+    }
+}
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.split_first() {
+        Some((command, rest)) if command == "repl" => return repl(rest),
+        Some(_) => usage(),
+        None => {}
+    }
+
     let runner = Runner::new(Language::Python);
     
     match runner.runtime.eval("x=42", Some("x")) {
@@ -0,0 +1,129 @@
+use std::path::PathBuf;
+
+use runtime::Runtime;
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+
+const PROMPT: &str = ">>> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HISTORY_FILE: &str = ".runner_history";
+
+const HELP: &str = "\
+,help         show this message
+,env          list the names bound in the global environment
+,load <file>  evaluate the contents of <file>
+,quit         leave the repl (or press Ctrl-D)";
+
+/// Meta-commands understood by the repl. They start with a comma and are only
+/// recognized at the start of an entry.
+enum Command {
+    Help,
+    Env,
+    Load(String),
+    Quit,
+    Unknown(String),
+}
+
+impl Command {
+    fn parse(line: &str) -> Option<Self> {
+        let line = line.trim().strip_prefix(',')?;
+        let (name, arg) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+
+        Some(match (name, arg.trim()) {
+            ("help", _) => Command::Help,
+            ("env", _) => Command::Env,
+            ("load", file) if !file.is_empty() => Command::Load(file.to_string()),
+            ("quit", _) => Command::Quit,
+            _ => Command::Unknown(line.to_string()),
+        })
+    }
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Runs an interactive read-eval-print loop against `runtime` until the user
+/// quits or closes the input.
+pub fn run(runtime: &dyn Runtime) -> rustyline::Result<()> {
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+
+    if let Some(history) = &history {
+        // a missing history file is expected on first use
+        let _ = editor.load_history(history);
+    }
+
+    let mut buffer = String::new();
+
+    loop {
+        let prompt = if buffer.is_empty() { PROMPT } else { CONTINUATION_PROMPT };
+
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                buffer.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e),
+        };
+
+        if buffer.is_empty() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(command) = Command::parse(&line) {
+                editor.add_history_entry(line.as_str())?;
+
+                match command {
+                    Command::Help => println!("{}", HELP),
+                    Command::Env => {
+                        let mut names = runtime.globals();
+                        names.sort();
+                        names.iter().for_each(|name| println!("{}", name));
+                    }
+                    Command::Load(file) => match std::fs::read_to_string(&file) {
+                        Ok(program) => {
+                            if let Err(e) = runtime.eval(&program, None) {
+                                eprintln!("{}", e);
+                            }
+                        }
+                        Err(e) => eprintln!("cannot read {}: {}", file, e),
+                    },
+                    Command::Quit => break,
+                    Command::Unknown(command) => {
+                        eprintln!("unknown command ,{} (try ,help)", command)
+                    }
+                }
+
+                continue;
+            }
+        } else {
+            buffer.push('\n');
+        }
+
+        buffer.push_str(&line);
+
+        if !runtime.is_complete(&buffer) {
+            continue;
+        }
+
+        editor.add_history_entry(buffer.as_str())?;
+
+        match runtime.eval_interactive(&buffer) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            Err(e) => eprintln!("{}", e),
+        }
+
+        buffer.clear();
+    }
+
+    if let Some(history) = &history {
+        editor.save_history(history)?;
+    }
+
+    Ok(())
+}
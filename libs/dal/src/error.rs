@@ -1,6 +1,10 @@
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[allow(clippy::enum_variant_names)]
 pub enum DalError {
+    #[error("lexer error")]
     LexerError,
+    #[error("parser error: {0}")]
     ParserError(String),
+    #[error("eval error: {0}")]
     EvalError(String),
 }
-
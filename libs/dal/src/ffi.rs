@@ -0,0 +1,260 @@
+//! Conversions between Rust values and Dal Objects, used to register Rust
+//! functions as Dal procedures.
+
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::error::DalError;
+use crate::object::{Object, to_integer, to_real};
+
+/// A Rust type that can be taken from a Dal Object.
+pub trait FromDal: Sized {
+    /// The kind of Object expected, for error messages.
+    const EXPECTED: &'static str;
+
+    fn from_dal(object: Object) -> Option<Self>;
+}
+
+/// A Rust type that can be turned into a Dal Object.
+pub trait IntoDal {
+    fn into_dal(self) -> Object;
+}
+
+impl FromDal for Object {
+    const EXPECTED: &'static str = "object";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        Some(object)
+    }
+}
+
+impl FromDal for bool {
+    const EXPECTED: &'static str = "boolean";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        match object {
+            Object::Bool(b) => Some(b),
+            _ => None,
+        }
+    }
+}
+
+impl FromDal for char {
+    const EXPECTED: &'static str = "character";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        match object {
+            Object::Char(c) => Some(c),
+            _ => None,
+        }
+    }
+}
+
+impl FromDal for i64 {
+    const EXPECTED: &'static str = "exact integer";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        match object {
+            Object::Number(n) => to_integer(&n),
+            _ => None,
+        }
+    }
+}
+
+impl FromDal for f64 {
+    const EXPECTED: &'static str = "real number";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        match object {
+            Object::Number(n) => to_real(&n),
+            _ => None,
+        }
+    }
+}
+
+impl FromDal for String {
+    const EXPECTED: &'static str = "string";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        match object {
+            Object::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl FromDal for Vec<u8> {
+    const EXPECTED: &'static str = "bytevector";
+
+    fn from_dal(object: Object) -> Option<Self> {
+        match object {
+            Object::Bytevector(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+impl IntoDal for Object {
+    fn into_dal(self) -> Object {
+        self
+    }
+}
+
+impl IntoDal for bool {
+    fn into_dal(self) -> Object {
+        Object::Bool(self)
+    }
+}
+
+impl IntoDal for char {
+    fn into_dal(self) -> Object {
+        Object::Char(self)
+    }
+}
+
+impl IntoDal for i64 {
+    fn into_dal(self) -> Object {
+        Object::Number(self.to_string())
+    }
+}
+
+impl IntoDal for f64 {
+    fn into_dal(self) -> Object {
+        let n = if self.is_nan() {
+            "+nan.0".to_string()
+        } else if self.is_infinite() {
+            if self > 0.0 { "+inf.0" } else { "-inf.0" }.to_string()
+        } else {
+            // Debug keeps the decimal point on integral values, so the number stays inexact
+            format!("{:?}", self)
+        };
+
+        Object::Number(n)
+    }
+}
+
+impl IntoDal for String {
+    fn into_dal(self) -> Object {
+        Object::String(self)
+    }
+}
+
+impl IntoDal for &str {
+    fn into_dal(self) -> Object {
+        Object::String(self.to_string())
+    }
+}
+
+impl IntoDal for Vec<u8> {
+    fn into_dal(self) -> Object {
+        Object::Bytevector(self)
+    }
+}
+
+/// A Rust function that can be registered as a Dal procedure.
+/// Implemented for functions of up to six FromDal arguments returning
+/// `Result<R, E>`, where R is IntoDal and E is reported as a DalError.
+pub trait HostFn<Args>: Send + Sync + 'static {
+    const ARITY: usize;
+
+    fn call(&self, name: &str, args: Vec<Object>) -> Result<Object, DalError>;
+}
+
+/// An async Rust function that can be registered as a Dal procedure.
+/// As HostFn, but the function returns a future of `Result<R, E>`.
+pub trait AsyncHostFn<Args>: Send + Sync + 'static {
+    const ARITY: usize;
+
+    fn call(&self, name: &str, args: Vec<Object>) -> BoxFuture<Result<Object, DalError>>;
+}
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+fn arity_error(name: &str, arity: usize, args: &[Object]) -> DalError {
+    DalError::EvalError(format!("{}: expected {} arguments, got {}", name, arity, args.len()))
+}
+
+fn argument<T: FromDal>(name: &str, i: usize, arg: Object) -> Result<T, DalError> {
+    T::from_dal(arg).ok_or_else(|| DalError::EvalError(format!(
+        "{}: expected {} as argument {}", name, T::EXPECTED, i + 1
+    )))
+}
+
+fn result<R: IntoDal, E: std::fmt::Display>(name: &str, result: Result<R, E>) -> Result<Object, DalError> {
+    result
+        .map(IntoDal::into_dal)
+        .map_err(|e| DalError::EvalError(format!("{}: {}", name, e)))
+}
+
+macro_rules! impl_host_fn {
+    ($($arg:ident),*) => {
+        impl<Fun, Ret, Error, $($arg,)*> HostFn<($($arg,)*)> for Fun
+        where
+            Fun: Fn($($arg),*) -> Result<Ret, Error> + Send + Sync + 'static,
+            Ret: IntoDal,
+            Error: std::fmt::Display,
+            $($arg: FromDal,)*
+        {
+            const ARITY: usize = <[&str]>::len(&[$(stringify!($arg)),*]);
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, name: &str, args: Vec<Object>) -> Result<Object, DalError> {
+                if args.len() != Self::ARITY {
+                    return Err(arity_error(name, Self::ARITY, &args));
+                }
+
+                let mut args = args.into_iter().enumerate();
+
+                $(
+                    let (i, arg) = args.next().expect("arity was checked");
+                    let $arg = argument::<$arg>(name, i, arg)?;
+                )*
+
+                result(name, self($($arg),*))
+            }
+        }
+
+        impl<Fun, Fut, Ret, Error, $($arg,)*> AsyncHostFn<($($arg,)*)> for Fun
+        where
+            Fun: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future<Output = Result<Ret, Error>> + Send + 'static,
+            Ret: IntoDal,
+            Error: std::fmt::Display,
+            $($arg: FromDal,)*
+        {
+            const ARITY: usize = <[&str]>::len(&[$(stringify!($arg)),*]);
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn call(&self, name: &str, args: Vec<Object>) -> BoxFuture<Result<Object, DalError>> {
+                if args.len() != Self::ARITY {
+                    let error = arity_error(name, Self::ARITY, &args);
+                    return Box::pin(async move { Err(error) });
+                }
+
+                let mut args = args.into_iter().enumerate();
+
+                $(
+                    let (i, arg) = args.next().expect("arity was checked");
+                    let $arg = match argument::<$arg>(name, i, arg) {
+                        Ok(arg) => arg,
+                        Err(error) => return Box::pin(async move { Err(error) }),
+                    };
+                )*
+
+                // arguments are converted before the call so the future owns only Rust values
+                let future = self($($arg),*);
+                let name = name.to_string();
+
+                Box::pin(async move { result(&name, future.await) })
+            }
+        }
+    };
+}
+
+impl_host_fn!();
+impl_host_fn!(A);
+impl_host_fn!(A, B);
+impl_host_fn!(A, B, C);
+impl_host_fn!(A, B, C, D);
+impl_host_fn!(A, B, C, D, E);
+impl_host_fn!(A, B, C, D, E, F);
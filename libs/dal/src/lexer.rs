@@ -4,6 +4,12 @@ use std::iter::Peekable;
 /// Token
 /// lexical analyzer based on r7rs small
 #[derive(Clone, Debug, Logos, PartialEq)]
+// the identifier grammar of r7rs small, with the non-ascii characters it allows
+#[logos(subpattern explicit_sign = r"\+|-")]
+#[logos(subpattern initial = r"[a-zA-Z]|[\p{Lu}\p{Ll}\p{Lt}\p{Lm}\p{Lo}\p{Mn}\p{Nl}\p{No}\p{Pd}\p{Pc}\p{Po}\p{Sc}\p{Sm}\p{Sk}\p{So}\p{Co}\x{200C}\x{200D}&&[^\x00-\x7F]]|[!\$%&\*/:<=>\?\^_~]")]
+#[logos(subpattern subsequent = r"(?&initial)|[0-9]|[\p{Nd}\p{Mc}\p{Me}&&[^\x00-\x7F]]|(?&explicit_sign)|\.|@")]
+#[logos(subpattern sign_subsequent = r"(?&initial)|(?&explicit_sign)|@")]
+#[logos(subpattern dot_subsequent = r"(?&sign_subsequent)|\.")]
 pub enum Token {
     #[regex(r"(#(([tT][rR][uU][eE])|([fF][aA][lL][sS][eE])|([tT]|[fF])))", to_bool)]
     Boolean(bool),
@@ -15,20 +21,19 @@ pub enum Token {
     CommaAt,
     #[regex(r"\.")]
     Dot,
-    #[regex(r"(;[^(\r\n|\r|\n)]*)", logos::skip)]
+    #[regex(r";[^\r\n]*", logos::skip)]
     Comment,
-    #[regex(r"((#!fold-case)|(#!no-fold-case))")]
-    Directive,
+    #[regex(r"((#!fold-case)|(#!no-fold-case))", to_directive)]
+    Directive(bool),
+    #[regex(r"(?&initial)(?&subsequent)*", to_identifier)]
     #[regex(
-        r"(([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])(([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|[0-9]|((\+|-)|\.|@))*)",
+        r"(?&explicit_sign)|(?&explicit_sign)(?&sign_subsequent)(?&subsequent)*|(?&explicit_sign)\.(?&dot_subsequent)(?&subsequent)*|\.(?&dot_subsequent)(?&subsequent)*",
         to_identifier
     )]
-    #[regex(r"(((\+|-)|((\+|-)(([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|(\+|-)|@)(([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|[0-9]|((\+|-)|\.|@))*)|((\+|-)\.((([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|(\+|-)|@)|\.)(([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|[0-9]|((\+|-)|\.|@))*)|(\.((([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|(\+|-)|@)|\.)(([a-zA-Z]|[!\$%&\*/:<=>\?\^_~])|[0-9]|((\+|-)|\.|@))*)))",
-            to_identifier)]
     Identifier(String),
     #[regex(
         r"(\|([^\|\\]|(\\x([0-9a-fA-F]+);)|(\\[aA]|\\[bB]|\\[tT]|\\[nN]|\\[rR])|(\\\|))*\|)",
-        to_identifier
+        to_vertical_line_identifier
     )]
     VerticalLineIdentifier(String),
     // prioritize Number over Identifier since +i and -i are valid identifiers and numbers according to the r7rs spec
@@ -48,7 +53,7 @@ pub enum Token {
     HashOpen,
     #[regex(r"#u8\(")]
     HashU8Open,
-    #[regex(r#""([^"\\]|(\\[aA]|\\[bB]|\\[tT]|\\[nN]|\\[rR])|\\"|\\|\\( |\t)*(\r\n|\r|\n)( |\t)*|(\\x([0-9a-fA-F]+);))*""#, to_string)]
+    #[regex(r#""([^"\\]|(\\[aA]|\\[bB]|\\[tT]|\\[nN]|\\[rR])|\\"|\\\\|\\( |\t)*(\r\n|\r|\n)( |\t)*|(\\x([0-9a-fA-F]+);))*""#, to_string)]
     String(String),
     #[regex(r"(( |\t)|(\r\n|\r|\n))")]
     Whitespace,
@@ -63,18 +68,23 @@ fn to_bool(lex: &mut Lexer<Token>) -> Option<bool> {
     }
 }
 
+/// true for #!fold-case, false for #!no-fold-case
+fn to_directive(lex: &mut Lexer<Token>) -> bool {
+    lex.slice() == "#!fold-case"
+}
+
 fn to_char(lex: &mut Lexer<Token>) -> Option<char> {
     let s = lex.slice();
+    let mut chars = s[2..].chars();
 
-    if s.len() == 3 {
-        Some(s[2..].chars().next().unwrap())
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        Some(c)
     } else {
         match &s[0..3] {
             "#\\x" => {
                 let hex = &s[3..];
-                let hex = u32::from_str_radix(hex, 16).unwrap();
-                let c = std::char::from_u32(hex).unwrap();
-                Some(c)
+                let hex = u32::from_str_radix(hex, 16).ok()?;
+                std::char::from_u32(hex)
             }
             _ => match &s[2..] {
                 "alarm" => Some('\u{0007}'),
@@ -100,11 +110,68 @@ fn to_number(lex: &mut Lexer<Token>) -> Option<String> {
     Some(lex.slice().to_string())
 }
 
+fn to_vertical_line_identifier(lex: &mut Lexer<Token>) -> Option<String> {
+    let s = lex.slice();
+    unescape(&s[1..s.len() - 1])
+}
+
 fn to_string(lex: &mut Lexer<Token>) -> Option<String> {
     let s = lex.slice();
+    unescape(&s[1..s.len() - 1])
+}
+
+/// Replaces the escape sequences allowed in strings and vertical line identifiers
+/// with the characters they stand for, and removes line continuations.
+/// Returns None for an escape sequence r7rs does not define.
+fn unescape(s: &str) -> Option<String> {
+    let mut result = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next()? {
+            'a' | 'A' => result.push('\u{0007}'),
+            'b' | 'B' => result.push('\u{0008}'),
+            't' | 'T' => result.push('\t'),
+            'n' | 'N' => result.push('\n'),
+            'r' | 'R' => result.push('\r'),
+            '"' => result.push('"'),
+            '\\' => result.push('\\'),
+            '|' => result.push('|'),
+            'x' | 'X' => {
+                let mut hex = String::new();
+                loop {
+                    match chars.next()? {
+                        ';' => break,
+                        c => hex.push(c),
+                    }
+                }
+                result.push(std::char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
+            }
+            // line continuation: \<intraline whitespace>*<line ending><intraline whitespace>*
+            c @ (' ' | '\t' | '\r' | '\n') => {
+                let mut c = c;
+                while c == ' ' || c == '\t' {
+                    c = chars.next()?;
+                }
+                match c {
+                    '\r' => {
+                        chars.next_if_eq(&'\n');
+                    }
+                    '\n' => {}
+                    _ => return None,
+                }
+                while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+            }
+            _ => return None,
+        }
+    }
 
-    let s: String = String::from(&s[1..s.len() - 1]);
-    Some(s)
+    Some(result)
 }
 
 /// DLexer
@@ -122,19 +189,11 @@ impl DLexer {
     pub fn new(input: &str) -> Self {
         Self {
             lexer: Token::lexer(input)
-                .map(|result| result)
                 .collect::<Vec<Result<Token, ()>>>()
                 .into_iter()
                 .peekable(),
         }
     }
-
-    pub fn peek(&mut self) -> Option<Result<Token, ()>> {
-        self.lexer.peek().map(|t: &Result<Token, ()>| match t {
-            Ok(t) => Ok(t.clone()),
-            Err(_) => Err(()),
-        })
-    }
 }
 
 impl Iterator for DLexer {
@@ -149,13 +208,13 @@ impl Iterator for DLexer {
         // If the next token is a boolean, character, directive, dot, identifier (without vertical lines), number
         // then we need to check if the lexeme after it starts with a delimiter.
         match self.lexer.peek()? {
-            Err(_) => Some(Err(())),
+            Err(_) => self.lexer.next(),
             Ok(t) => {
                 match t {
                     Token::Boolean(_)
                     | Token::Char(_)
                     | Token::Dot
-                    | Token::Directive
+                    | Token::Directive(_)
                     | Token::Identifier(_)
                     | Token::Number(_) => {
                         let token = self.lexer.next()?; // Consume the token
@@ -184,7 +243,8 @@ impl Iterator for DLexer {
                         while let Some(Ok(Token::Whitespace)) = self.lexer.peek() {
                             self.lexer.next();
                         }
-                        self.lexer.next()
+                        // the token after the whitespace still needs its delimiter checked
+                        self.next()
                     }
                     _ => self.lexer.next(),
                 }
@@ -8,11 +8,21 @@
 //! The main components of the interpreter are:
 //! - Machine: the interpreter itself
 //! - Object: the data types of the language
+//! - Parser: the reader, turning source text into Objects
+//! - Printer: the Display impl of Object, turning Objects back into source text
 //!
 
 mod error;
+mod ffi;
 mod lexer;
 mod object;
 mod parser;
+mod printer;
 mod machine;
 
+pub use error::DalError;
+pub use ffi::{AsyncHostFn, BoxFuture, FromDal, HostFn, IntoDal};
+pub use machine::Machine;
+pub use object::{Object, Procedure};
+pub use parser::Parser;
+
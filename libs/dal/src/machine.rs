@@ -1,8 +1,6 @@
-use crate::error::DalError;
-use crate::lexer::{DLexer, Token};
-use crate::object::{Atom, Object, Sexp};
+use crate::ffi::{AsyncHostFn, HostFn};
+use crate::object::{Object, Procedure};
 use crate::parser::Parser;
-use logos::Logos;
 use uuid::Uuid;
 use std::collections::HashMap;
 
@@ -98,10 +96,112 @@ impl Machine {
     //     Ok(result)
     // }
 
+    /// Binds a Rust function as a global procedure called `name`.
+    /// Arguments are converted with FromDal, and the arity and argument types are
+    /// checked on every call; an `Err` returned by `f` becomes a DalError.
+    pub fn register_fn<Args, F: HostFn<Args>>(&mut self, name: &str, f: F) {
+        let procedure_name = name.to_string();
+        let procedure = Procedure::new(name, move |args| f.call(&procedure_name, args));
+        self.global_env.insert(name.to_string(), Object::Procedure(procedure));
+    }
+
+    /// Binds an async Rust function as a global procedure called `name`.
+    /// As register_fn, but a call suspends the Dal computation until the
+    /// returned future resolves, leaving the executor free in the meantime.
+    pub fn register_async_fn<Args, F: AsyncHostFn<Args>>(&mut self, name: &str, f: F) {
+        let procedure_name = name.to_string();
+        let procedure = Procedure::new_async(name, move |args| f.call(&procedure_name, args));
+        self.global_env.insert(name.to_string(), Object::Procedure(procedure));
+    }
+
     pub async fn eval(&mut self, code: &str) -> Result<Object, Box<dyn std::error::Error + Send + Sync>> {
-        let mut _parser = Parser::new(code);
+        // the program is read so syntax errors are reported; evaluation is not implemented yet
+        for datum in Parser::new(code) {
+            datum?;
+        }
+
         Ok(Object::Null)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::DalError;
+
+    async fn call(machine: &Machine, name: &str, args: Vec<Object>) -> Result<Object, DalError> {
+        match machine.global_env.get(name) {
+            Some(Object::Procedure(procedure)) => procedure.call(args).await,
+            _ => panic!("{} is not a procedure", name),
+        }
+    }
 
+    fn number(n: &str) -> Object {
+        Object::Number(n.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_register_fn() {
+        let mut machine = Machine::new();
+        machine.register_fn("fetch-user", |id: i64| match id {
+            42 => Ok(format!("user {}", id)),
+            _ => Err("no such user"),
+        });
+        machine.register_fn("scale", |x: f64, by: f64| Ok::<_, String>(x * by));
+        machine.register_fn("answer", || Ok::<_, String>(42));
+
+        assert_eq!(call(&machine, "fetch-user", vec![number("42")]).await, Ok(Object::String("user 42".to_string())));
+        assert_eq!(call(&machine, "fetch-user", vec![number("#x2a")]).await, Ok(Object::String("user 42".to_string())));
+        assert_eq!(call(&machine, "scale", vec![number("1/2"), number("4")]).await, Ok(number("2.0")));
+        assert_eq!(call(&machine, "scale", vec![number("+inf.0"), number("-1")]).await, Ok(number("-inf.0")));
+        assert_eq!(call(&machine, "answer", vec![]).await, Ok(number("42")));
+    }
+
+    #[tokio::test]
+    async fn test_register_fn_errors() {
+        let mut machine = Machine::new();
+        machine.register_fn("fetch-user", |id: i64| match id {
+            42 => Ok(format!("user {}", id)),
+            _ => Err("no such user"),
+        });
+
+        assert_eq!(
+            call(&machine, "fetch-user", vec![number("7")]).await,
+            Err(DalError::EvalError("fetch-user: no such user".to_string()))
+        );
+        assert_eq!(
+            call(&machine, "fetch-user", vec![number("1.5")]).await,
+            Err(DalError::EvalError("fetch-user: expected exact integer as argument 1".to_string()))
+        );
+        assert_eq!(
+            call(&machine, "fetch-user", vec![]).await,
+            Err(DalError::EvalError("fetch-user: expected 1 arguments, got 0".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_register_async_fn() {
+        let mut machine = Machine::new();
+        machine.register_async_fn("fetch-user", |id: i64| async move {
+            tokio::task::yield_now().await;
+            match id {
+                42 => Ok(format!("user {}", id)),
+                _ => Err("no such user"),
+            }
+        });
+
+        assert_eq!(call(&machine, "fetch-user", vec![number("42")]).await, Ok(Object::String("user 42".to_string())));
+        assert_eq!(
+            call(&machine, "fetch-user", vec![number("7")]).await,
+            Err(DalError::EvalError("fetch-user: no such user".to_string()))
+        );
+        assert_eq!(
+            call(&machine, "fetch-user", vec![Object::Bool(true)]).await,
+            Err(DalError::EvalError("fetch-user: expected exact integer as argument 1".to_string()))
+        );
+        assert_eq!(
+            call(&machine, "fetch-user", vec![]).await,
+            Err(DalError::EvalError("fetch-user: expected 1 arguments, got 0".to_string()))
+        );
+    }
+}
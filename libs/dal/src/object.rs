@@ -1,6 +1,10 @@
-use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::DalError;
+use crate::ffi::BoxFuture;
 
 /// Represents a Dal Object
+#[derive(Clone, Debug, PartialEq)]
 pub enum Object {
     Bool(bool),
     Bytevector(Vec<u8>),
@@ -9,33 +13,102 @@ pub enum Object {
     Null,
     Number(String),
     Pair(Box<Object>, Box<Object>),
+    Procedure(Procedure),
     String(String),
     Symbol(String),
     Vector(Vec<Object>),
 }
 
-#[derive(Debug)]
-pub enum Atom {
-    Bool(bool),
-    Bytevector(Vec<u8>),
-    Char(char),
-    Eof,
-    Null,
-    Number(String),
-    String(String),
-    Symbol(String),
+/// Parses an exact integer lexeme, accepting an optional radix prefix.
+pub(crate) fn to_integer(n: &str) -> Option<i64> {
+    let (radix, digits) = match n.get(0..2) {
+        Some("#x" | "#X") => (16, &n[2..]),
+        Some("#d" | "#D") => (10, &n[2..]),
+        Some("#o" | "#O") => (8, &n[2..]),
+        Some("#b" | "#B") => (2, &n[2..]),
+        _ => (10, n),
+    };
+
+    i64::from_str_radix(digits, radix).ok()
+}
+
+/// Parses a decimal real lexeme, including rationals and the r7rs infinities and NaNs.
+pub(crate) fn to_real(n: &str) -> Option<f64> {
+    match n.to_ascii_lowercase().as_str() {
+        "+inf.0" => Some(f64::INFINITY),
+        "-inf.0" => Some(f64::NEG_INFINITY),
+        "+nan.0" | "-nan.0" => Some(f64::NAN),
+        n => match n.split_once('/') {
+            Some((numerator, denominator)) => Some(numerator.parse::<f64>().ok()? / denominator.parse::<f64>().ok()?),
+            None => to_integer(n).map(|n| n as f64).or_else(|| n.parse().ok()),
+        },
+    }
 }
 
+/// A procedure implemented in Rust
+#[derive(Clone)]
+pub struct Procedure {
+    name: String,
+    function: Function,
+}
+
+type SyncFunction = dyn Fn(Vec<Object>) -> Result<Object, DalError> + Send + Sync;
+type AsyncFunction = dyn Fn(Vec<Object>) -> BoxFuture<Result<Object, DalError>> + Send + Sync;
 
-#[derive(Debug)]
-pub enum Sexp {
-    Atom(Atom),
-    Pair(Box<Sexp>, Box<Sexp>),
+#[derive(Clone)]
+enum Function {
+    Sync(Arc<SyncFunction>),
+    Async(Arc<AsyncFunction>),
 }
 
-impl Sexp {
-    pub fn eval(&self, _: &mut HashMap<String, Object>) -> Result<Object, Box<dyn std::error::Error + Send + Sync>> {
-        Ok(Object::Number("42".to_string()))
+impl Procedure {
+    pub fn new<F>(name: &str, function: F) -> Self
+    where
+        F: Fn(Vec<Object>) -> Result<Object, DalError> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            function: Function::Sync(Arc::new(function)),
+        }
+    }
+
+    pub fn new_async<F>(name: &str, function: F) -> Self
+    where
+        F: Fn(Vec<Object>) -> BoxFuture<Result<Object, DalError>> + Send + Sync + 'static,
+    {
+        Self {
+            name: name.to_string(),
+            function: Function::Async(Arc::new(function)),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Calls the procedure. Synchronous procedures complete on the first poll;
+    /// asynchronous ones suspend the caller until their future resolves.
+    pub async fn call(&self, args: Vec<Object>) -> Result<Object, DalError> {
+        match &self.function {
+            Function::Sync(function) => function(args),
+            Function::Async(function) => function(args).await,
+        }
+    }
+}
+
+impl std::fmt::Debug for Procedure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#<procedure {}>", self.name)
     }
 }
 
+/// Procedures are only equal to themselves, as with eqv?
+impl PartialEq for Procedure {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.function, &other.function) {
+            (Function::Sync(a), Function::Sync(b)) => Arc::ptr_eq(a, b),
+            (Function::Async(a), Function::Async(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
@@ -1,43 +1,59 @@
+use std::iter::Peekable;
+
 use crate::lexer::{DLexer, Token};
-use crate::object::{Atom, Sexp};
+use crate::object::{Object, to_integer};
 use crate::error::DalError;
 
+/// How deeply data may nest before reading them fails, rather than overflowing the stack.
+const MAX_DEPTH: usize = 256;
+
+/// Parser
+/// Reads the external representation of Dal data (r7rs small datum syntax) and
+/// returns it as Objects. Programs are data, so this is also how code is read.
 pub struct Parser {
-    tokens: DLexer,
+    tokens: Peekable<DLexer>,
+    fold_case: bool,
+    depth: usize,
 }
 
 impl Parser {
     pub fn new(code: &str) -> Self {
 
         Self {
-            tokens: DLexer::new(code),
+            tokens: DLexer::new(code).peekable(),
+            fold_case: false,
+            depth: 0,
         }
     }
 
-    fn peek(&mut self) -> Option<Result<Token, ()>> {
-        self.tokens.peek()
-    }
+    fn peek(&mut self) -> Option<Result<Token, DalError>> {
+        self.directives();
 
-    fn expect_token(&mut self, expected: Token) -> Result<Token, DalError> {
-        self.peek()
-        .ok_or(DalError::ParserError("expected something".to_string()))
-        .and_then(|result|
+        self.tokens.peek().map(|result|
             result
+            .clone()
             .map_err(|_| DalError::LexerError)
-            .and_then(|token| {
-                if token == expected {
-                    Ok(token)
-                } else {
-                    Err(DalError::ParserError(format!("expected {:?}", expected)))
-                }
-            })
         )
-        .inspect(|_| {self.tokens.next(); })
     }
 
-    fn paren_left(&mut self) -> Result<(), DalError> {
-        self.expect_token(Token::ParenLeft)
-        .map(|_| ())
+    fn next_token(&mut self) -> Result<Token, DalError> {
+        self.directives();
+
+        self.tokens
+        .next()
+        .ok_or(DalError::ParserError("unexpected end of input".to_string()))
+        .and_then(|result| result.map_err(|_| DalError::LexerError))
+    }
+
+    fn expect_token(&mut self, expected: Token) -> Result<Token, DalError> {
+        self.next_token()
+        .and_then(|token| {
+            if token == expected {
+                Ok(token)
+            } else {
+                Err(DalError::ParserError(format!("expected {:?}, found {:?}", expected, token)))
+            }
+        })
     }
 
     fn paren_right(&mut self) -> Result<(), DalError> {
@@ -45,69 +61,296 @@ impl Parser {
         .map(|_| ())
     }
 
+    /// Reads data until the closing parenthesis, which is consumed.
+    fn data_until_paren_right(&mut self) -> Result<Vec<Object>, DalError> {
+        let mut data = vec![];
 
-    fn true_(&mut self) -> Result<Sexp, DalError> {
-        self.expect_token(Token::Boolean(true))
-        .map(|_| Sexp::Atom(Atom::Bool(true)))
+        while self.peek().transpose()? != Some(Token::ParenRight) {
+            data.push(self.datum()?);
+        }
+
+        self.paren_right()
+        .map(|_| data)
     }
 
-    fn false_(&mut self) -> Result<Sexp, DalError> {
-        self.expect_token(Token::Boolean(false))
-        .map(|_| Sexp::Atom(Atom::Bool(false)))
+    /// The opening parenthesis has been consumed.
+    fn list(&mut self) -> Result<Object, DalError> {
+        let mut cars = vec![];
+
+        let cdr = loop {
+            match self.peek().transpose()? {
+                Some(Token::ParenRight) => {
+                    self.paren_right()?;
+                    break Object::Null;
+                }
+                Some(Token::Dot) if !cars.is_empty() => {
+                    self.expect_token(Token::Dot)?;
+                    let cdr = self.datum()?;
+                    self.paren_right()?;
+                    break cdr;
+                }
+                _ => cars.push(self.datum()?),
+            }
+        };
+
+        Ok(cars
+            .into_iter()
+            .rev()
+            .fold(cdr, |cdr, car| Object::Pair(Box::new(car), Box::new(cdr))))
     }
 
-    fn boolean(&mut self) -> Result<Sexp, DalError> {
-        self.true_()
-        .or_else(|_| self.false_())
+    /// The opening `#u8(` has been consumed.
+    fn bytevector(&mut self) -> Result<Object, DalError> {
+        self.data_until_paren_right()?
+        .into_iter()
+        .map(|datum| match datum {
+            Object::Number(n) => to_byte(&n).ok_or(DalError::ParserError(format!("expected byte, found {}", n))),
+            _ => Err(DalError::ParserError("expected byte".to_string())),
+        })
+        .collect::<Result<Vec<u8>, DalError>>()
+        .map(Object::Bytevector)
     }
 
+    /// Reads `'datum` and friends as `(quote datum)`.
+    fn abbreviation(&mut self, symbol: &str) -> Result<Object, DalError> {
+        self.datum()
+        .map(|datum| Object::Pair(
+            Box::new(Object::Symbol(symbol.to_string())),
+            Box::new(Object::Pair(Box::new(datum), Box::new(Object::Null))),
+        ))
+    }
 
-    fn get(&mut self, expected: Token) -> Result<(), DalError> {
-        self.tokens.peek()
-        .map(|result|
-            result
-            .map_err(|e| DalError::LexerError)
-            .and_then(|token| {
-                if token == expected {
-                    Ok(())
-                } else {
-                    Err(DalError::ParserError(format!("expected {:?}", expected)))
-                }
-            })
-        )
-        .unwrap_or(Err(DalError::ParserError("expected something".to_string())))
+    fn symbol(&self, identifier: String) -> Object {
+        if self.fold_case {
+            Object::Symbol(identifier.chars().map(fold_case).collect())
+        } else {
+            Object::Symbol(identifier)
+        }
     }
 
-    fn one_or_more<F, T>(&mut self, mut f: F) -> Result<(), DalError>
-    where
-        F: FnMut(&mut Self) -> Option<Result<T, DalError>>
-    {
-        f(self).ok_or(DalError::ParserError("expected one or more".to_string()))?;
+    fn datum(&mut self) -> Result<Object, DalError> {
+        if self.depth == MAX_DEPTH {
+            return Err(DalError::ParserError(format!("data nested more than {} deep", MAX_DEPTH)));
+        }
 
-        while let Some(result) = f(self) {
-            match result {
-                Ok(_) => {},
-                Err(e) => return Err(e),
-            }
+        self.depth += 1;
+        let datum = self.nested_datum();
+        self.depth -= 1;
+        datum
+    }
+
+    fn nested_datum(&mut self) -> Result<Object, DalError> {
+        match self.next_token()? {
+            Token::Boolean(b) => Ok(Object::Bool(b)),
+            Token::Char(c) => Ok(Object::Char(c)),
+            Token::Number(n) => Ok(Object::Number(n)),
+            Token::String(s) => Ok(Object::String(s)),
+            Token::Identifier(s) => Ok(self.symbol(s)),
+            Token::VerticalLineIdentifier(s) => Ok(Object::Symbol(s)),
+            Token::ParenLeft => self.list(),
+            Token::HashOpen => self.data_until_paren_right().map(Object::Vector),
+            Token::HashU8Open => self.bytevector(),
+            Token::Quote => self.abbreviation("quote"),
+            Token::Quasiquote => self.abbreviation("quasiquote"),
+            Token::Comma => self.abbreviation("unquote"),
+            Token::CommaAt => self.abbreviation("unquote-splicing"),
+            token => Err(DalError::ParserError(format!("unexpected {:?}", token))),
         }
+    }
 
-        Ok(())
+    /// Consumes any `#!fold-case` / `#!no-fold-case` directives before the next token.
+    /// Like comments, they may appear anywhere between tokens.
+    fn directives(&mut self) {
+        while let Some(Ok(Token::Directive(fold_case))) = self.tokens.peek() {
+            self.fold_case = *fold_case;
+            self.tokens.next();
+        }
     }
+}
+
+/// Approximates Unicode simple case folding, which `char-foldcase` uses, with
+/// `char::to_lowercase`. A character whose lowercase is longer than itself, like
+/// İ, is left alone, as simple folding maps each character to a single one. Unlike
+/// `str::to_lowercase`, a word-final Σ is not mapped to ς.
+fn fold_case(c: char) -> char {
+    let mut lower = c.to_lowercase();
 
-    fn sexp(&mut self) -> Result<Sexp, DalError> {
-        unimplemented!("sexp")
+    match (lower.next(), lower.next()) {
+        (Some(lower), None) => lower,
+        _ => c,
     }
 }
 
+/// Converts a number lexeme to a byte, accepting an optional radix prefix.
+fn to_byte(n: &str) -> Option<u8> {
+    to_integer(n).and_then(|n| u8::try_from(n).ok())
+}
+
 impl std::iter::Iterator for Parser {
-    type Item = Result<Sexp, DalError>;
+    type Item = Result<Object, DalError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.directives();
+
         self.tokens
         .peek()
-        .and_then(|_|
-            Some(self.sexp()))
-        
+        .is_some()
+        .then(|| self.datum())
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(code: &str) -> Result<Vec<Object>, DalError> {
+        Parser::new(code).collect()
+    }
+
+    fn symbol(s: &str) -> Object {
+        Object::Symbol(s.to_string())
+    }
+
+    fn list(objects: Vec<Object>) -> Object {
+        objects
+            .into_iter()
+            .rev()
+            .fold(Object::Null, |cdr, car| Object::Pair(Box::new(car), Box::new(cdr)))
+    }
+
+    #[test]
+    fn test_read_atoms() {
+        let data = read(r#"#t #false #\a #\space #\x41 42 -1.5e3 "a\tb\x41;" foo |a b| "#).unwrap();
+
+        assert_eq!(data, vec![
+            Object::Bool(true),
+            Object::Bool(false),
+            Object::Char('a'),
+            Object::Char(' '),
+            Object::Char('A'),
+            Object::Number("42".to_string()),
+            Object::Number("-1.5e3".to_string()),
+            Object::String("a\tbA".to_string()),
+            symbol("foo"),
+            symbol("a b"),
+        ]);
+    }
+
+    #[test]
+    fn test_read_string_escapes() {
+        let data = read("\"\\\\\" \"a\\\"b\" \"a\\  \n  b\" #\\λ").unwrap();
+
+        assert_eq!(data, vec![
+            Object::String("\\".to_string()),
+            Object::String("a\"b".to_string()),
+            Object::String("ab".to_string()),
+            Object::Char('λ'),
+        ]);
+    }
+
+    #[test]
+    fn test_read_unicode_identifiers() {
+        let data = read("λ (café x² a\u{0301}) 日本語 x٣ →").unwrap();
+
+        assert_eq!(data, vec![
+            symbol("λ"),
+            list(vec![symbol("café"), symbol("x²"), symbol("a\u{0301}")]),
+            symbol("日本語"),
+            symbol("x٣"),
+            symbol("→"),
+        ]);
+
+        // decimal digits may not start an identifier, ascii or not
+        assert!(read("٣x").is_err());
+    }
+
+    #[test]
+    fn test_read_peculiar_identifiers() {
+        let data = read("+ - ... +a -@x ->λ +.x .λ ..").unwrap();
+        let names = ["+", "-", "...", "+a", "-@x", "->λ", "+.x", ".λ", ".."];
+
+        assert_eq!(data, names.iter().map(|name| symbol(name)).collect::<Vec<_>>());
+        assert!(read("+٣").is_err());
+    }
+
+    #[test]
+    fn test_read_compound() {
+        let data = read("(a (b . c) #(1 #\\x) #u8(0 #xff) ())").unwrap();
+
+        assert_eq!(data, vec![list(vec![
+            symbol("a"),
+            Object::Pair(Box::new(symbol("b")), Box::new(symbol("c"))),
+            Object::Vector(vec![Object::Number("1".to_string()), Object::Char('x')]),
+            Object::Bytevector(vec![0, 255]),
+            Object::Null,
+        ])]);
+    }
+
+    #[test]
+    fn test_read_abbreviations() {
+        let data = read("'a `(b ,c ,@d)").unwrap();
+
+        assert_eq!(data, vec![
+            list(vec![symbol("quote"), symbol("a")]),
+            list(vec![symbol("quasiquote"), list(vec![
+                symbol("b"),
+                list(vec![symbol("unquote"), symbol("c")]),
+                list(vec![symbol("unquote-splicing"), symbol("d")]),
+            ])]),
+        ]);
+    }
+
+    #[test]
+    fn test_read_fold_case() {
+        let data = read("Foo #!fold-case Foo |Foo| ΣΑΣ #!no-fold-case Foo").unwrap();
+
+        assert_eq!(data, vec![symbol("Foo"), symbol("foo"), symbol("Foo"), symbol("σασ"), symbol("Foo")]);
+
+        // İ lowercases to two characters, but folds to itself
+        assert_eq!(read("#!fold-case İx").unwrap(), vec![symbol("İx")]);
+    }
+
+    #[test]
+    fn test_read_directives_inside_data() {
+        let data = read("(a #!fold-case B #(C) '#!no-fold-case D) E").unwrap();
+
+        assert_eq!(data, vec![
+            list(vec![
+                symbol("a"),
+                symbol("b"),
+                Object::Vector(vec![symbol("c")]),
+                list(vec![symbol("quote"), symbol("D")]),
+            ]),
+            symbol("E"),
+        ]);
+    }
+
+    #[test]
+    fn test_read_comments() {
+        let data = read("a ; (b c), 'd |e|\nf ;\r\n;; g").unwrap();
+
+        assert_eq!(data, vec![symbol("a"), symbol("f")]);
+    }
+
+    #[test]
+    fn test_read_depth_is_limited() {
+        let nested = format!("{}{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(read(&nested).is_ok());
+
+        assert!(matches!(read(&"(".repeat(200_000)), Err(DalError::ParserError(_))));
+        assert!(matches!(read(&"'".repeat(200_000)), Err(DalError::ParserError(_))));
+        assert!(matches!(read(&format!("#({})", "#(".repeat(MAX_DEPTH))), Err(DalError::ParserError(_))));
+    }
+
+    #[test]
+    fn test_read_errors() {
+        assert!(matches!(read("(a b"), Err(DalError::ParserError(_))));
+        assert!(matches!(read(")"), Err(DalError::ParserError(_))));
+        assert!(matches!(read("(. a)"), Err(DalError::ParserError(_))));
+        assert!(matches!(read("(a . b c)"), Err(DalError::ParserError(_))));
+        assert!(matches!(read("#u8(256)"), Err(DalError::ParserError(_))));
+        assert!(matches!(read("12abc"), Err(DalError::LexerError)));
+        // lexer errors are consumed, so reading always makes progress
+        assert_eq!(Parser::new("\"a\\q\" b").count(), 4);
+    }
+}
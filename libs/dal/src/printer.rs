@@ -0,0 +1,192 @@
+use std::fmt::{self, Display, Formatter, Write};
+
+use crate::lexer::{DLexer, Token};
+use crate::object::Object;
+
+/// Printer
+/// Displays Objects in their external representation, as r7rs `write` does,
+/// so that anything readable prints as text the Parser reads back as an equal Object.
+impl Display for Object {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Object::Bool(true) => write!(f, "#t"),
+            Object::Bool(false) => write!(f, "#f"),
+            Object::Bytevector(v) => {
+                write!(f, "#u8(")?;
+                separated(f, v.iter())?;
+                write!(f, ")")
+            }
+            Object::Char(c) => write_char(f, *c),
+            Object::Eof => write!(f, "#<eof>"),
+            Object::Null => write!(f, "()"),
+            Object::Number(n) => write!(f, "{}", n),
+            Object::Pair(car, cdr) => {
+                write!(f, "({}", car)?;
+
+                let mut cdr = cdr.as_ref();
+                while let Object::Pair(car, next) = cdr {
+                    write!(f, " {}", car)?;
+                    cdr = next.as_ref();
+                }
+
+                match cdr {
+                    Object::Null => write!(f, ")"),
+                    cdr => write!(f, " . {})", cdr),
+                }
+            }
+            Object::Procedure(procedure) => write!(f, "{:?}", procedure),
+            Object::String(s) => {
+                f.write_char('"')?;
+                s.chars().try_for_each(|c| write_escaped(f, c, '"'))?;
+                f.write_char('"')
+            }
+            Object::Symbol(s) if is_identifier(s) => write!(f, "{}", s),
+            Object::Symbol(s) => {
+                f.write_char('|')?;
+                s.chars().try_for_each(|c| write_escaped(f, c, '|'))?;
+                f.write_char('|')
+            }
+            Object::Vector(v) => {
+                write!(f, "#(")?;
+                separated(f, v.iter())?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn separated<T: Display>(f: &mut Formatter<'_>, mut items: impl Iterator<Item = T>) -> fmt::Result {
+    if let Some(first) = items.next() {
+        write!(f, "{}", first)?;
+    }
+
+    items.try_for_each(|item| write!(f, " {}", item))
+}
+
+fn write_char(f: &mut Formatter<'_>, c: char) -> fmt::Result {
+    match c {
+        '\u{0007}' => write!(f, "#\\alarm"),
+        '\u{0008}' => write!(f, "#\\backspace"),
+        '\u{007F}' => write!(f, "#\\delete"),
+        '\u{001B}' => write!(f, "#\\escape"),
+        '\n' => write!(f, "#\\newline"),
+        '\u{0000}' => write!(f, "#\\null"),
+        '\r' => write!(f, "#\\return"),
+        ' ' => write!(f, "#\\space"),
+        '\t' => write!(f, "#\\tab"),
+        c if c.is_control() || c.is_whitespace() => write!(f, "#\\x{:x}", c as u32),
+        c => write!(f, "#\\{}", c),
+    }
+}
+
+/// Writes a character of a string or |symbol|, escaping the delimiter, backslash
+/// and anything that would not survive being read back verbatim.
+fn write_escaped(f: &mut Formatter<'_>, c: char, delimiter: char) -> fmt::Result {
+    match c {
+        '\u{0007}' => write!(f, "\\a"),
+        '\u{0008}' => write!(f, "\\b"),
+        '\t' => write!(f, "\\t"),
+        '\n' => write!(f, "\\n"),
+        '\r' => write!(f, "\\r"),
+        '"' if delimiter == '"' => write!(f, "\\\""),
+        '|' if delimiter == '|' => write!(f, "\\|"),
+        // \\ is not a valid escape inside |symbols|, the hex escape is valid in both
+        '\\' if delimiter == '"' => write!(f, "\\\\"),
+        c if c == '\\' || c.is_control() => write!(f, "\\x{:x};", c as u32),
+        c => f.write_char(c),
+    }
+}
+
+/// Whether `s` reads back as the symbol `s` without vertical lines.
+fn is_identifier(s: &str) -> bool {
+    let mut tokens = DLexer::new(s);
+
+    matches!(
+        (tokens.next(), tokens.next()),
+        (Some(Ok(Token::Identifier(identifier))), None) if identifier == s
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffi::IntoDal;
+    use crate::parser::Parser;
+    use proptest::prelude::*;
+
+    fn read(code: &str) -> Vec<Object> {
+        Parser::new(code)
+            .collect::<Result<Vec<Object>, _>>()
+            .unwrap_or_else(|e| panic!("cannot read {}: {}", code, e))
+    }
+
+    fn number() -> impl Strategy<Value = Object> {
+        prop_oneof![
+            any::<i64>().prop_map(|n| n.to_string()),
+            any::<f64>().prop_map(|n| match n.into_dal() {
+                Object::Number(n) => n,
+                _ => unreachable!(),
+            }),
+            (any::<i32>(), 1..u32::MAX).prop_map(|(n, d)| format!("{}/{}", n, d)),
+            any::<u64>().prop_map(|n| format!("#x{:X}", n)),
+            any::<u64>().prop_map(|n| format!("#o{:o}", n)),
+            any::<i64>().prop_map(|n| format!("#b{:b}", n)),
+            (any::<i32>(), any::<u32>()).prop_map(|(re, im)| format!("{}+{}i", re, im)),
+            prop::sample::select(vec!["+inf.0", "-inf.0", "+nan.0", "-nan.0", "+i", "-i", ".5", "#e1.5", "#i1/3"])
+                .prop_map(str::to_string),
+        ]
+        .prop_map(Object::Number)
+    }
+
+    fn symbol() -> impl Strategy<Value = Object> {
+        prop_oneof![
+            "[a-z!$%&*/:<=>?^_~][a-z0-9!$%&*/:<=>?^_~+.@-]*",
+            prop::sample::select(vec!["+", "-", "...", "->x", "+.a", ".", "+i", "1", "", "λ", "a b", "|", "\\"])
+                .prop_map(str::to_string),
+            any::<String>(),
+        ]
+        .prop_map(Object::Symbol)
+    }
+
+    fn object() -> impl Strategy<Value = Object> {
+        let leaf = prop_oneof![
+            any::<bool>().prop_map(Object::Bool),
+            any::<char>().prop_map(Object::Char),
+            Just(Object::Null),
+            number(),
+            any::<String>().prop_map(Object::String),
+            symbol(),
+            any::<Vec<u8>>().prop_map(Object::Bytevector),
+        ];
+
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                (inner.clone(), inner.clone()).prop_map(|(car, cdr)| Object::Pair(Box::new(car), Box::new(cdr))),
+                prop::collection::vec(inner, 0..8).prop_map(Object::Vector),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_read_write_round_trip(object in object()) {
+            prop_assert_eq!(read(&object.to_string()), vec![object]);
+        }
+
+        #[test]
+        fn test_read_write_round_trip_sequence(objects in prop::collection::vec(object(), 0..8)) {
+            let code = objects.iter().map(Object::to_string).collect::<Vec<_>>().join(" ");
+            prop_assert_eq!(read(&code), objects);
+        }
+    }
+
+    #[test]
+    fn test_write() {
+        let data = read(r#"(a "x\ny\"" #\space #\λ |a b| |1| #(1 #u8(2 3)) (b . c) () #t)"#);
+
+        assert_eq!(
+            data[0].to_string(),
+            r#"(a "x\ny\"" #\space #\λ |a b| |1| #(1 #u8(2 3)) (b . c) () #t)"#
+        );
+    }
+}
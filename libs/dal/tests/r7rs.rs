@@ -0,0 +1,168 @@
+//! r7rs conformance
+//!
+//! Runs the `.scm` files under `tests/r7rs` through `Machine::eval` and reports
+//! how many cases pass in each section of the report they are grouped by. The
+//! files use the forms of chibi-scheme's r7rs-tests: `(test-begin "section")`,
+//! `(test-end)`, `(test expected expr)`, `(test-assert expr)` and
+//! `(test-error expr)`. Any other top-level form is evaluated as it is read.
+//!
+//! `(test-values expected expr)` is also understood, and `(test-exit)` ignored,
+//! so chibi's own suite runs unchanged.
+//!
+//! The report is printed rather than asserted, so that it tracks what Dal
+//! supports without failing the build; see it with
+//! `cargo test -p dal --test r7rs -- --nocapture`. The harness fails only when
+//! a file cannot be read or holds a malformed test form.
+//!
+//! `r7rs-small.scm` is written for Dal, mostly from the examples in the report,
+//! which may be copied freely. chibi's `tests/r7rs-tests.scm` is under chibi's
+//! BSD license, and may be added next to it together with that license's notice.
+
+use std::fs;
+use std::path::Path;
+
+use dal::{Machine, Object, Parser};
+
+/// The outcome of the cases in one section.
+struct Section {
+    name: String,
+    passed: usize,
+    failures: Vec<String>,
+}
+
+impl Section {
+    fn new(name: &str) -> Self {
+        Section {
+            name: name.to_string(),
+            passed: 0,
+            failures: vec![],
+        }
+    }
+
+    fn record(&mut self, case: &Object, failure: Option<String>) {
+        match failure {
+            None => self.passed += 1,
+            Some(reason) => self.failures.push(format!("{} ({})", case, reason)),
+        }
+    }
+}
+
+/// The elements of a proper list, or None for anything else.
+fn elements(list: &Object) -> Option<Vec<&Object>> {
+    let mut elements = vec![];
+    let mut rest = list;
+
+    while let Object::Pair(car, cdr) = rest {
+        elements.push(car.as_ref());
+        rest = cdr;
+    }
+
+    matches!(rest, Object::Null).then_some(elements)
+}
+
+/// Evaluates `program` and describes why it failed unless it returned #t.
+async fn expect_true(machine: &mut Machine, program: &str) -> Option<String> {
+    match machine.eval(program).await {
+        Ok(Object::Bool(true)) => None,
+        Ok(result) => Some(format!("got {}", result)),
+        Err(e) => Some(format!("error: {}", e)),
+    }
+}
+
+/// Runs one case, returning why it failed, if it did.
+async fn run(machine: &mut Machine, form: &str, args: &[&Object]) -> Option<String> {
+    match (form, args) {
+        ("test", [expected, expr]) => match expect_true(machine, &format!("(equal? {} {})", expected, expr)).await {
+            None => None,
+            // evaluated again on its own, to show what it gives instead
+            Some(_) => match machine.eval(&expr.to_string()).await {
+                Ok(result) => Some(format!("expected {}, got {}", expected, result)),
+                Err(e) => Some(format!("expected {}, error: {}", expected, e)),
+            },
+        },
+        ("test-assert", [expr]) => expect_true(machine, &format!("(if {} #t #f)", expr)).await,
+        ("test-values", [expected, expr]) => {
+            let values = |expr: &Object| format!("(call-with-values (lambda () {}) list)", expr);
+            expect_true(machine, &format!("(equal? {} {})", values(expected), values(expr))).await
+        }
+        ("test-error", [expr]) => match machine.eval(&expr.to_string()).await {
+            Ok(result) => Some(format!("expected an error, got {}", result)),
+            Err(_) => None,
+        },
+        (form, _) => panic!("malformed {} form", form),
+    }
+}
+
+async fn run_file(path: &Path, sections: &mut Vec<Section>) {
+    let code = fs::read_to_string(path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+    let mut machine = Machine::new();
+    let mut current = Section::new(&format!("{} (between sections)", path.display()));
+
+    for datum in Parser::new(&code) {
+        let datum = datum.unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e));
+        let list = elements(&datum).unwrap_or_default();
+
+        let form = match list.first() {
+            Some(Object::Symbol(form)) => form.as_str(),
+            _ => "",
+        };
+
+        match (form, &list[..]) {
+            ("test-begin", [_, Object::String(name)]) => {
+                sections.push(std::mem::replace(&mut current, Section::new(name)));
+            }
+            ("test-end", _) => {
+                let after = Section::new(&format!("{} (between sections)", path.display()));
+                sections.push(std::mem::replace(&mut current, after));
+            }
+            ("test-exit", _) => {}
+            ("test" | "test-assert" | "test-values" | "test-error", [_, args @ ..]) => {
+                let failure = run(&mut machine, form, args).await;
+                current.record(&datum, failure);
+            }
+            (form, _) if form.starts_with("test-") => {
+                current.record(&datum, Some("not supported by the harness".to_string()));
+            }
+            _ => {
+                if let Err(e) = machine.eval(&datum.to_string()).await {
+                    current.record(&datum, Some(format!("setup failed: {}", e)));
+                }
+            }
+        }
+    }
+
+    sections.push(current);
+}
+
+#[tokio::test]
+async fn r7rs_conformance() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/r7rs");
+    let mut files: Vec<_> = fs::read_dir(&directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "scm"))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "no .scm files in {}", directory.display());
+
+    let mut sections = vec![];
+    for file in &files {
+        run_file(file, &mut sections).await;
+    }
+    sections.retain(|section| section.passed + section.failures.len() > 0);
+
+    let passed: usize = sections.iter().map(|section| section.passed).sum();
+    let total: usize = sections.iter().map(|section| section.passed + section.failures.len()).sum();
+    assert!(total > 0, "no test cases found");
+
+    println!("r7rs conformance: {}/{} cases pass", passed, total);
+    for section in &sections {
+        println!("  {:<40} {:>3}/{}", section.name, section.passed, section.passed + section.failures.len());
+    }
+
+    for section in &sections {
+        for failure in &section.failures {
+            println!("FAIL [{}] {}", section.name, failure);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+;; Conformance cases for Dal, grouped by the section of the r7rs small report
+;; they exercise. Most are the examples given in the report itself.
+;;
+;; (test expected expr) passes when (equal? expected expr) is #t,
+;; (test-assert expr) when expr is true, (test-values expected expr) when both
+;; give equal lists of values, and (test-error expr) when evaluating
+;; expr raises an error. Other top-level forms are evaluated as they are read.
+
+(test-begin "2 Lexical conventions")
+(test 'abc '|abc|)
+(test "λ" (symbol->string 'λ))
+(test #\x41 #\A)
+(test 10 #xA)
+(test 5 #b101)
+(test 'foo (quote #!fold-case FOO))
+(test-end)
+
+(test-begin "4.1 Primitive expression types")
+(test 28 (let ((x 28)) x))
+(test 'a (quote a))
+(test '#(a b c) (quote #(a b c)))
+(test '(+ 1 2) (quote (+ 1 2)))
+(test '(quote a) ''a)
+(test "abc" '"abc")
+(test 145932 '145932)
+(test #t '#t)
+(test 7 (+ 3 4))
+(test 12 ((if #f + *) 3 4))
+(test 8 ((lambda (x) (+ x x)) 4))
+(test '(3 4 5 6) ((lambda x x) 3 4 5 6))
+(test '(5 6) ((lambda (x y . z) z) 3 4 5 6))
+(test 'yes (if (> 3 2) 'yes 'no))
+(test 'no (if (> 2 3) 'yes 'no))
+(test 1 (if (> 3 2) (- 3 2) (+ 3 2)))
+(test 5 (let ((x 2)) (set! x 5) x))
+(test-end)
+
+(test-begin "4.2 Derived expression types")
+(test 'greater (cond ((> 3 2) 'greater) ((< 3 2) 'less)))
+(test 'equal (cond ((> 3 3) 'greater) ((< 3 3) 'less) (else 'equal)))
+(test 2 (cond ((assv 'b '((a 1) (b 2))) => cadr) (else #f)))
+(test 'composite (case (* 2 3) ((2 3 5 7) 'prime) ((1 4 6 8 9) 'composite)))
+(test 'c (case (car '(c d)) ((a e i o u) 'vowel) ((w y) 'semivowel) (else => (lambda (x) x))))
+(test #t (and (= 2 2) (> 2 1)))
+(test #f (and 1 2 'c '(f g) #f))
+(test #t (and))
+(test #t (or (= 2 2) (> 2 1)))
+(test '(b c) (or (memq 'b '(a b c)) (/ 3 0)))
+(test 6 (let ((x 2) (y 3)) (* x y)))
+(test 35 (let ((x 2) (y 3)) (let ((x 7) (z (+ x y))) (* z x))))
+(test 70 (let ((x 2) (y 3)) (let* ((x 7) (z (+ x y))) (* z x))))
+(test #t (letrec ((even? (lambda (n) (if (zero? n) #t (odd? (- n 1)))))
+                  (odd? (lambda (n) (if (zero? n) #f (even? (- n 1))))))
+           (even? 88)))
+(test 35 (let-values (((a b) (values 1 2)) ((x y) (values 3 4))) (* (+ a b) (+ x y))))
+(test '#(0 1 2 3 4) (do ((vec (make-vector 5)) (i 0 (+ i 1))) ((= i 5) vec) (vector-set! vec i i)))
+(test '((6 1 3) (-5 -2)) (let loop ((numbers '(3 -2 1 6 -5)) (nonneg '()) (neg '()))
+                            (cond ((null? numbers) (list nonneg neg))
+                                  ((>= (car numbers) 0) (loop (cdr numbers) (cons (car numbers) nonneg) neg))
+                                  ((< (car numbers) 0) (loop (cdr numbers) nonneg (cons (car numbers) neg))))))
+(test 3 (force (delay (+ 1 2))))
+(test '(list 3 4) `(list ,(+ 1 2) 4))
+(test '(a 3 4 5 6 b) `(a ,(+ 1 2) ,@(map abs '(4 -5 6)) b))
+(test '#(10 5 2 4 3 8) `#(10 5 ,(sqrt 4) ,@(map sqrt '(16 9)) 8))
+(test-end)
+
+(test-begin "4.3 Macros")
+(test 'now (let-syntax ((given-that (syntax-rules () ((_ test stmt1 stmt2 ...) (if test (begin stmt1 stmt2 ...))))))
+             (let ((if #t)) (given-that if (set! if 'now)) if)))
+(test 7 (letrec-syntax ((my-or (syntax-rules () ((my-or) #f) ((my-or e) e)
+                                                  ((my-or e1 e2 ...) (let ((temp e1)) (if temp temp (my-or e2 ...)))))))
+          (let ((x #f) (y 7) (temp 8) (let odd?) (if even?)) (my-or x (let temp) (if y) y))))
+(test-end)
+
+(test-begin "5 Program structure")
+(define add3 (lambda (x) (+ x 3)))
+(test 6 (add3 3))
+(define first car)
+(test 1 (first '(1 2)))
+(test 45 (let ((x 5)) (define foo (lambda (y) (bar x y))) (define bar (lambda (a b) (+ (* a b) a))) (foo (+ x 3))))
+(define-values (q r) (floor/ 7 2))
+(test '(3 1) (list q r))
+(test-end)
+
+(test-begin "6.1 Equivalence predicates")
+(test #t (eqv? 'a 'a))
+(test #f (eqv? 'a 'b))
+(test #t (eqv? '() '()))
+(test #f (eqv? (cons 1 2) (cons 1 2)))
+(test #t (let ((p (lambda (x) x))) (eqv? p p)))
+(test #t (eq? 'a 'a))
+(test #t (equal? '(a (b) c) '(a (b) c)))
+(test #t (equal? "abc" "abc"))
+(test #t (equal? (make-vector 5 'a) (make-vector 5 'a)))
+(test-end)
+
+(test-begin "6.2 Numbers")
+(test #t (complex? 3+4i))
+(test #t (rational? 6/10))
+(test #t (integer? 3.0))
+(test #f (exact? 3.0))
+(test #t (exact-integer? 32))
+(test 4 (max 3 4))
+(test 7 (+ 3 4))
+(test -1 (- 3 4))
+(test 1/2 (/ 2 4))
+(test 7 (abs -7))
+(test -3 (floor-quotient -5 2))
+(test 1 (floor-remainder -5 2))
+(test -2 (truncate-quotient -5 2))
+(test 4 (gcd 32 -36))
+(test 288 (lcm 32 -36))
+(test -5.0 (floor -4.3))
+(test 4.0 (round 3.5))
+(test 4 (round 7/2))
+(test 1000 (expt 10 3))
+(test 100 (string->number "100"))
+(test 256 (string->number "100" 16))
+(test "255" (number->string 255))
+(test-end)
+
+(test-begin "6.3 Booleans")
+(test #f (not 3))
+(test #t (not #f))
+(test #f (not '()))
+(test #t (boolean? #f))
+(test #f (boolean? '()))
+(test #t (boolean=? #t #t))
+(test-end)
+
+(test-begin "6.4 Pairs and lists")
+(test '(a b c) (cons 'a '(b c)))
+(test '(a . 3) (cons 'a 3))
+(test 'a (car '(a b c)))
+(test '(b c d) (cdr '(a b c d)))
+(test #t (list? '(a b c)))
+(test #f (list? '(a . b)))
+(test '(a 7 c) (list 'a (+ 3 4) 'c))
+(test 3 (length '(a (b) (c d e))))
+(test '(a b c d) (append '(a) '(b c d)))
+(test '(c b a) (reverse '(a b c)))
+(test 'c (list-ref '(a b c d) 2))
+(test '(a b c) (memq 'a '(a b c)))
+(test '((a) c) (member (list 'a) '(b (a) c)))
+(test '(b 2) (assq 'b '((a 1) (b 2))))
+(test '(1 2 3) (list-copy '(1 2 3)))
+(test-error (car '()))
+(test-end)
+
+(test-begin "6.5 Symbols")
+(test #t (symbol? 'foo))
+(test "flying-fish" (symbol->string 'flying-fish))
+(test 'mISSISSIppi (string->symbol "mISSISSIppi"))
+(test #t (symbol=? 'a 'a 'a))
+(test-end)
+
+(test-begin "6.6 Characters")
+(test #t (char<? #\a #\b #\c))
+(test #t (char-alphabetic? #\a))
+(test #t (char-numeric? #\1))
+(test 3 (digit-value #\3))
+(test #\A (char-upcase #\a))
+(test #\a (char-foldcase #\A))
+(test 955 (char->integer #\λ))
+(test-end)
+
+(test-begin "6.7 Strings")
+(test 3 (string-length "abc"))
+(test #\b (string-ref "abc" 1))
+(test "bc" (substring "abc" 1 3))
+(test "abcdef" (string-append "abc" "def"))
+(test '(#\a #\b) (string->list "ab"))
+(test "ABC" (string-upcase "abc"))
+(test #t (string=? "a" "a" "a"))
+(test #t (string<? "abc" "abd"))
+(test "aaa" (make-string 3 #\a))
+(test-end)
+
+(test-begin "6.8 Vectors")
+(test 8 (vector-ref '#(1 1 2 3 5 8 13 21) 5))
+(test '#(0 ("Sue" "Sue") "Anna") (let ((vec (vector 0 '(2 2 2 2) "Anna"))) (vector-set! vec 1 '("Sue" "Sue")) vec))
+(test '(dah dah didah) (vector->list '#(dah dah didah)))
+(test '#(1 2 3) (list->vector '(1 2 3)))
+(test 3 (vector-length '#(a b c)))
+(test-end)
+
+(test-begin "6.9 Bytevectors")
+(test #u8(12 12) (make-bytevector 2 12))
+(test 8 (bytevector-u8-ref #u8(1 1 2 3 5 8 13 21) 5))
+(test #u8(1 2 3 4 5) (bytevector-append #u8(1 2) #u8(3 4 5)))
+(test "A" (utf8->string #u8(#x41)))
+(test-end)
+
+(test-begin "6.10 Control features")
+(test #t (procedure? car))
+(test 7 (apply + (list 3 4)))
+(test '(b e h) (map cadr '((a b) (d e) (g h))))
+(test '#(b e h) (vector-map cadr '#((a b) (d e) (g h))))
+(test -3 (call-with-current-continuation (lambda (exit) (for-each (lambda (x) (if (negative? x) (exit x))) '(54 0 37 -3 245 19)) #t)))
+(test 5 (call-with-values (lambda () (values 4 5)) (lambda (a b) b)))
+(test-values (values 2 1) (floor/ 5 2))
+(test-end)
+
+(test-begin "6.11 Exceptions")
+(test 42 (with-exception-handler (lambda (e) 42) (lambda () (+ (raise-continuable 'oops) 0))))
+(test 'caught (guard (e (#t 'caught)) (raise 'boom)))
+(test-error (raise 'boom))
+(test-error (error "bad thing" 1 2))
+(test-end)
+
+(test-begin "6.13 Input and output")
+(test "abc" (let ((port (open-output-string))) (write-string "abc" port) (get-output-string port)))
+(test #\a (read-char (open-input-string "abc")))
+(test '(a b) (read (open-input-string "(a b)")))
+(test #t (eof-object? (read-char (open-input-string ""))))
+(test-end)
@@ -0,0 +1,154 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::BitOr;
+
+/// Who Dust operations are carried out for: a user or program, or the root
+/// principal, which may do anything.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Principal {
+    Root,
+    User(String),
+}
+
+impl Principal {
+    pub fn user(name: &str) -> Self {
+        Principal::User(name.to_string())
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::Root => write!(f, "root"),
+            Principal::User(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A set of things a principal may do to a node.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Permissions(u8);
+
+impl Permissions {
+    pub const NONE: Permissions = Permissions(0);
+    /// Look keys up in the node, list it and walk it.
+    pub const READ: Permissions = Permissions(1);
+    /// Bind and unbind keys in the node.
+    pub const WRITE: Permissions = Permissions(2);
+    /// Make nodes under the node, or restore deleted ones.
+    pub const CREATE_CHILD: Permissions = Permissions(4);
+    /// Delete the node.
+    pub const DELETE: Permissions = Permissions(8);
+    pub const ALL: Permissions = Permissions(15);
+
+    pub fn contains(self, other: Permissions) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub(crate) fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub(crate) fn from_bits(bits: u8) -> Option<Self> {
+        (bits & !Self::ALL.0 == 0).then_some(Permissions(bits))
+    }
+}
+
+impl BitOr for Permissions {
+    type Output = Permissions;
+
+    fn bitor(self, other: Permissions) -> Permissions {
+        Permissions(self.0 | other.0)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Self::READ, "read"),
+            (Self::WRITE, "write"),
+            (Self::CREATE_CHILD, "create-child"),
+            (Self::DELETE, "delete"),
+        ];
+        let names: Vec<&str> = names
+            .iter()
+            .filter(|(permission, _)| self.contains(*permission))
+            .map(|(_, name)| *name)
+            .collect();
+
+        match names.is_empty() {
+            true => write!(f, "none"),
+            false => write!(f, "{}", names.join(", ")),
+        }
+    }
+}
+
+/// What principals may do to a node. A node without an ACL of its own has the
+/// one of its nearest ancestor that has one; where no node up to the root has
+/// one, everyone may do everything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Acl {
+    users: BTreeMap<String, Permissions>,
+    others: Permissions,
+}
+
+impl Acl {
+    /// An ACL giving `others` to every user it does not name.
+    pub fn new(others: Permissions) -> Self {
+        Acl {
+            users: BTreeMap::new(),
+            others,
+        }
+    }
+
+    /// Gives `user` `permissions`, instead of those given to others.
+    pub fn grant(mut self, user: &str, permissions: Permissions) -> Self {
+        self.users.insert(user.to_string(), permissions);
+        self
+    }
+
+    pub fn permissions(&self, principal: &Principal) -> Permissions {
+        match principal {
+            Principal::Root => Permissions::ALL,
+            Principal::User(name) => self.users.get(name).copied().unwrap_or(self.others),
+        }
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = (&str, Permissions)> {
+        self.users.iter().map(|(name, permissions)| (name.as_str(), *permissions))
+    }
+
+    pub fn others(&self) -> Permissions {
+        self.others
+    }
+}
+
+/// Whether `principal` has every one of `permissions` on a node whose ACL,
+/// whether its own or inherited, is `acl`.
+pub(crate) fn permits(acl: Option<&Acl>, principal: &Principal, permissions: Permissions) -> bool {
+    match acl {
+        Some(acl) => acl.permissions(principal).contains(permissions),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions() {
+        let acl = Acl::new(Permissions::READ).grant("alice", Permissions::READ | Permissions::WRITE);
+
+        assert_eq!(acl.permissions(&Principal::user("alice")).to_string(), "read, write");
+        assert_eq!(acl.permissions(&Principal::user("bob")), Permissions::READ);
+        assert_eq!(acl.permissions(&Principal::Root), Permissions::ALL);
+        assert!(permits(Some(&acl), &Principal::user("alice"), Permissions::WRITE));
+        assert!(!permits(Some(&acl), &Principal::user("bob"), Permissions::READ | Permissions::WRITE));
+        assert!(permits(None, &Principal::user("bob"), Permissions::ALL));
+
+        assert_eq!(Permissions::NONE.to_string(), "none");
+        assert_eq!(Permissions::from_bits(Permissions::ALL.bits()), Some(Permissions::ALL));
+        assert_eq!(Permissions::from_bits(16), None);
+    }
+}
@@ -1,78 +1,1510 @@
-
+mod acl;
 mod path;
 mod object;
+mod storage;
 mod symboltable;
+mod transaction;
+mod watch;
 
 use std::cell::RefCell;
-use std::rc::{Rc, Weak};
-use thiserror;
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+
+pub use acl::{Acl, Permissions, Principal};
+pub use object::{Object, ObjectKind};
+pub use path::Path;
+pub use storage::{FileStorage, MemoryStorage, Operation, Storage};
+pub use symboltable::{Entry, SymbolTable, Version, Walk};
+pub use transaction::Transaction;
+pub use watch::{Event, EventStream, Subscription};
 
-use object::Object;
-use path::Path;
-use symboltable::SymbolTable;
+use symboltable::{read, write};
+use watch::Watchers;
 
 #[derive(Debug, thiserror::Error)]
-enum DustError {
+pub enum DustError {
+    #[error("DustError: {0}")]
     SymbolTableError(String),
+    #[error("DustError: {0}")]
+    PathError(String),
+    #[error("DustError: {0}")]
+    StorageError(String),
+    #[error("DustError: {0}")]
+    RevisionError(String),
+    #[error("DustError: {0}")]
+    PermissionError(String),
+}
+
+/// How many versions of each key are kept unless `Dust::with_history` says otherwise.
+const DEFAULT_HISTORY: usize = 16;
+
+/// Fails unless `principal` has `permissions` on the node at `path`, whose ACL,
+/// its own or inherited, is `acl`.
+fn require(acl: Option<&Acl>, principal: &Principal, path: &Path, permissions: Permissions) -> Result<(), DustError> {
+    if *principal == Principal::Root || acl::permits(acl, principal, permissions) {
+        return Ok(());
+    }
+
+    Err(DustError::PermissionError(format!(
+        "{} lacks {} permission on {}",
+        principal, permissions, path
+    )))
+}
+
+/// Fails if the node at `absolute`, reached as `path` from the `current` node, is
+/// one that cannot be deleted: the root, or the current node or one of its ancestors.
+fn deletable(current: &Path, absolute: &Path, path: &Path) -> Result<(), DustError> {
+    if *absolute == Path::root() {
+        return Err(DustError::SymbolTableError("Cannot delete the root node".to_string()));
+    }
+
+    if current.includes(absolute) {
+        return Err(DustError::SymbolTableError(format!("Cannot delete the current node or its ancestors: {}", path)));
+    }
+
+    Ok(())
+}
+
+/// The node at the absolute `path` below `head`.
+fn resolve<'a>(head: &'a SymbolTable, path: &Path) -> Result<&'a SymbolTable, DustError> {
+    head.node(path)
+        .ok_or_else(|| DustError::SymbolTableError(format!("Path not found: {}", path)))
+}
+
+/// The ACL of the node at the absolute `path` below `head`, its own or inherited.
+/// For a path that does not exist, that of its nearest ancestor that does.
+fn inherited_acl<'a>(head: &'a SymbolTable, path: &Path) -> Option<&'a Acl> {
+    let mut current = head;
+    let mut acl = current.acl();
+
+    for key in path.as_vector() {
+        match current.get_child(&key) {
+            Some(child) => current = child,
+            None => break,
+        }
+
+        acl = current.acl().or(acl);
+    }
+
+    acl
+}
+
+/// Fails unless `principal` has `permissions` on the node at the absolute `path`
+/// below `head`.
+fn check(head: &SymbolTable, principal: &Principal, path: &Path, permissions: Permissions) -> Result<(), DustError> {
+    require(inherited_acl(head, path), principal, path, permissions)
+}
+
+/// Like `check`, for every node of the subtree under `node`.
+fn check_subtree(
+    head: &SymbolTable,
+    principal: &Principal,
+    node: &SymbolTable,
+    permissions: Permissions,
+) -> Result<(), DustError> {
+    if *principal == Principal::Root {
+        return Ok(());
+    }
+
+    let mut paths = vec![];
+    node.visit(&mut |node| paths.push(node.path().clone()));
+    paths.iter().try_for_each(|path| check(head, principal, path, permissions))
+}
+
+/// What the cursors on a tree share.
+struct Tree {
+    /// The root node, holding the whole tree. Held for reading by every read and
+    /// for writing while a change is made, so that a read sees a change, however
+    /// many nodes it spans, whole or not at all. Taken after `storage`.
+    head: RwLock<SymbolTable>,
+    /// Held by every change from recording it to making it, so that changes reach
+    /// the storage in the order they are made to the tree. Reads do not take it.
+    storage: Mutex<Box<dyn Storage>>,
+    watchers: Watchers,
+    /// Counts the changes made to the tree. Only advanced with the storage locked.
+    revision: AtomicU64,
+    history: AtomicUsize,
+}
+
+impl Tree {
+    /// Locks the storage to make a change. Fails if this thread holds it already,
+    /// as it does while running a transaction's closure or a watch callback, where
+    /// waiting for it would wait forever.
+    fn lock(&self) -> Result<StorageGuard<'_>, DustError> {
+        let tree = self as *const Tree as usize;
+        if HELD.with(|held| held.borrow().contains(&tree)) {
+            return Err(DustError::SymbolTableError(
+                "Cannot change the tree from within a transaction or watch callback".to_string(),
+            ));
+        }
+
+        let storage = self.storage.lock().unwrap_or_else(PoisonError::into_inner);
+        HELD.with(|held| held.borrow_mut().push(tree));
+        Ok(StorageGuard { storage, tree })
+    }
+
+    /// The tree, kept from changing until the guard is dropped.
+    fn nodes(&self) -> RwLockReadGuard<'_, SymbolTable> {
+        read(&self.head)
+    }
+
+    /// Whether `principal` may hear of `event`, one of `changes`: it may read the
+    /// node changed, or where that is gone, its nearest ancestor. A deleted node
+    /// is read as it was before it was deleted.
+    fn may_hear(&self, principal: &Principal, event: &Event, changes: &Changes) -> bool {
+        let path = event.path();
+        match event {
+            Event::NodeDeleted(_) if changes.deleted.contains_key(path) => {
+                require(changes.deleted[path].as_ref(), principal, path, Permissions::READ).is_ok()
+            }
+            _ => check(&self.nodes(), principal, path, Permissions::READ).is_ok(),
+        }
+    }
+
+    /// Tells the watchers about `changes`.
+    fn notify(&self, changes: &Changes) {
+        self.watchers.notify(&changes.events, |principal, event| self.may_hear(principal, event, changes));
+    }
+
+    fn revision(&self) -> u64 {
+        self.revision.load(Ordering::SeqCst)
+    }
+
+    /// Records `operation` as the next revision, makes the change, and tells the
+    /// watchers about it. Returns the new revision.
+    fn commit(&self, storage: &mut Box<dyn Storage>, operation: Operation) -> Result<u64, DustError> {
+        let revision = self.revision() + 1;
+        storage.append(revision, &operation)?;
+
+        let mut changes = Changes::default();
+        self.apply(&mut write(&self.head), revision, &operation, &mut changes);
+        self.compact(storage);
+        self.notify(&changes);
+        Ok(revision)
+    }
+
+    /// Makes the change described by `operation` at `revision` to the tree under
+    /// `head`, without recording it, and adds what it did to `changes`.
+    fn apply(&self, head: &mut SymbolTable, revision: u64, operation: &Operation, changes: &mut Changes) {
+        let history = self.history.load(Ordering::SeqCst);
+        self.revision.fetch_max(revision, Ordering::SeqCst);
+
+        match operation {
+            Operation::MakeNode(path) => {
+                make_node(head, path, changes);
+            }
+            Operation::DeleteNode(path) => {
+                let acl = inherited_acl(head, path).cloned();
+                let parent = path.parent().and_then(|parent| head.node_mut(&parent));
+                if let (Some(parent), Some(name)) = (parent, path.file_name())
+                    && parent.remove_child(name).is_some()
+                {
+                    changes.deleted.entry(path.clone()).or_insert(acl);
+                    changes.events.push(Event::NodeDeleted(path.clone()));
+                }
+            }
+            Operation::Set(path, key, value) => {
+                make_node(head, path, changes).set(key, value.clone(), revision, history);
+                changes.events.push(Event::KeySet {
+                    node: path.clone(),
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+            Operation::Remove(path, key) => {
+                if let Some(node) = head.node_mut(path)
+                    && node.remove(key, revision, history).is_some()
+                {
+                    changes.events.push(Event::KeyRemoved {
+                        node: path.clone(),
+                        key: key.clone(),
+                    });
+                }
+            }
+            Operation::SetAcl(path, acl) => {
+                if let Some(node) = head.node_mut(path) {
+                    node.set_acl(acl.clone());
+                }
+            }
+            Operation::Trimmed(path, key) => {
+                if let Some(node) = head.node_mut(path) {
+                    node.set_trimmed(key);
+                }
+            }
+            Operation::Batch(operations) => {
+                operations.iter().for_each(|operation| self.apply(head, revision, operation, changes));
+            }
+        }
+    }
+
+    /// Compacts `storage` once it asks for it, after a change has been made.
+    fn compact(&self, storage: &mut Box<dyn Storage>) {
+        if storage.wants_compaction() {
+            let revision = self.revision();
+            let mut snapshot = vec![];
+            self.nodes().visit(&mut |node| node.snapshot(revision, &mut snapshot));
+
+            // a failed compaction leaves the log as it was, and is retried after the next change
+            let _ = storage.compact(&snapshot);
+        }
+    }
+}
+
+thread_local! {
+    /// The trees, by address, whose storage this thread holds.
+    static HELD: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+/// A tree's storage, locked by `Tree::lock`.
+struct StorageGuard<'a> {
+    storage: MutexGuard<'a, Box<dyn Storage>>,
+    tree: usize,
+}
+
+impl Deref for StorageGuard<'_> {
+    type Target = Box<dyn Storage>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.storage
+    }
+}
+
+impl DerefMut for StorageGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.storage
+    }
+}
+
+impl Drop for StorageGuard<'_> {
+    fn drop(&mut self) {
+        HELD.with(|held| held.borrow_mut().retain(|tree| *tree != self.tree));
+    }
 }
 
-impl std::fmt::Display for DustError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "DustError: {}", self)
+/// Walks the absolute `path` down from `head`, creating any missing nodes, and
+/// returns the last one. Adds an event for each node created.
+fn make_node<'a>(head: &'a mut SymbolTable, path: &Path, changes: &mut Changes) -> &'a mut SymbolTable {
+    let mut made = vec![];
+    let node = head.make_node(path, &mut made);
+    changes.events.extend(made.into_iter().map(Event::NodeCreated));
+    node
+}
+
+/// What a change did, for the watchers.
+#[derive(Default)]
+struct Changes {
+    events: Vec<Event>,
+    /// The ACL, its own or inherited, each deleted node had before the change.
+    deleted: HashMap<Path, Option<Acl>>,
+}
+
+/// A cursor on a tree of nodes holding Objects.
+///
+/// Every change is recorded in the tree's Storage before it is made, so a tree
+/// opened from the same Storage comes back as it was.
+///
+/// The tree can be shared between threads: `cursor` gives another cursor on it,
+/// with a current node of its own. The whole tree is guarded by a single
+/// reader-writer lock, rather than one per node: reads run concurrently, while
+/// changes are made one at a time with the tree locked for writing, so a read
+/// sees each change, transactions included, whole or not at all.
+///
+/// Each cursor acts for a Principal, and every operation through it fails with
+/// a PermissionError unless the ACLs of the nodes involved allow the principal
+/// to carry it out. `new` and `open` give cursors acting for the root principal,
+/// which can give out cursors acting for others with `cursor_as`.
+pub struct Dust {
+    tree: Arc<Tree>,
+    current: Path,
+    principal: Principal,
+}
+
+/// A subtree removed from a tree by `Dust::delete_node`. Nothing in it can be read
+/// or changed until `Dust::restore_node` puts it back.
+#[derive(Clone)]
+pub struct DeletedNode {
+    node: SymbolTable,
+    /// The id of the node it was removed from.
+    parent: u64,
+}
+
+impl DeletedNode {
+    /// The path the subtree was removed from.
+    pub fn path(&self) -> Path {
+        self.node.path().clone()
     }
 }
 
-struct Dust {
-    head: Rc<RefCell<SymbolTable>>,
-    current: Rc<RefCell<SymbolTable>>,
+impl Default for Dust {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Dust {
+    /// An empty tree that lives only in memory.
     pub fn new() -> Self {
-        let root = SymbolTable::new(None, Path::Absolute(vec!["/".to_string()]));
+        Self::with_storage(Box::new(MemoryStorage))
+    }
 
-        let head = Rc::new(RefCell::new(root));
-        let current = head.clone();
+    /// Rebuilds the tree recorded in `storage`, which goes on recording its changes.
+    pub fn open(storage: impl Storage + 'static) -> Result<Self, DustError> {
+        let dust = Self::with_storage(Box::new(storage));
 
+        let operations = dust.tree.lock()?.load()?;
+        let mut head = write(&dust.tree.head);
+        for (revision, operation) in operations {
+            dust.tree.apply(&mut head, revision, &operation, &mut Changes::default());
+        }
+        drop(head);
+
+        Ok(dust)
+    }
+
+    fn with_storage(storage: Box<dyn Storage>) -> Self {
+        let root = SymbolTable::new(Path::root());
+
+        Dust {
+            tree: Arc::new(Tree {
+                head: RwLock::new(root),
+                storage: Mutex::new(storage),
+                watchers: Watchers::default(),
+                revision: AtomicU64::new(0),
+                history: AtomicUsize::new(DEFAULT_HISTORY),
+            }),
+            current: Path::root(),
+            principal: Principal::Root,
+        }
+    }
+
+    /// Keeps up to `limit` versions of each key, rather than 16, dropping the
+    /// oldest versions of keys that already have more.
+    pub fn with_history(self, limit: usize) -> Self {
+        {
+            // this thread may hold the storage already, in a transaction or a watch
+            // callback, which keeps other changes out all the same
+            let _storage = self.tree.lock();
+            self.tree.history.store(limit.max(1), Ordering::SeqCst);
+            write(&self.tree.head).visit_mut(&mut |node| node.trim_history(limit));
+        }
+
+        self
+    }
+
+    /// Another cursor on the same tree, starting at this one's current node and
+    /// acting for the same principal.
+    pub fn cursor(&self) -> Dust {
         Dust {
-            head,
-            current,
+            tree: self.tree.clone(),
+            current: self.current.clone(),
+            principal: self.principal.clone(),
+        }
+    }
+
+    /// Like `cursor`, acting for `principal`. Only a cursor acting for the root
+    /// principal can give out cursors acting for another.
+    pub fn cursor_as(&self, principal: Principal) -> Result<Dust, DustError> {
+        if self.principal != Principal::Root && self.principal != principal {
+            return Err(DustError::PermissionError(format!(
+                "{} cannot act for {}",
+                self.principal, principal
+            )));
         }
+
+        Ok(Dust {
+            principal,
+            ..self.cursor()
+        })
+    }
+
+    /// The principal this cursor acts for.
+    pub fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    /// The path of the current node.
+    pub fn current(&self) -> &Path {
+        &self.current
+    }
+
+    /// Resolves `path` against the current node, giving a normalized absolute path.
+    fn absolute(&self, path: &Path) -> Path {
+        self.current.join(path)
     }
 
-    pub fn make_node(&mut self, path: Path) -> Result<Option<Weak<RefCell<SymbolTable>>>, DustError> {
-        Ok(SymbolTable::make_node(self.current.clone(), path))
+    /// Makes the node at `path` and any missing ancestors, and returns its absolute
+    /// path. The principal needs create-child permission on the node's parent, or if
+    /// that is missing too, on the last node that exists; it does even when the node
+    /// is already there, in which case nothing is recorded.
+    pub fn make_node(&self, path: Path) -> Result<Path, DustError> {
+        let path = self.absolute(&path);
+
+        let mut storage = self.tree.lock()?;
+        let exists = {
+            let head = self.tree.nodes();
+            let parent = path.parent().unwrap_or_else(Path::root);
+            check(&head, &self.principal, &parent, Permissions::CREATE_CHILD)?;
+            head.node(&path).is_some()
+        };
+
+        if !exists {
+            self.tree.commit(&mut storage, Operation::MakeNode(path.clone()))?;
+        }
+
+        Ok(path)
     }
 
     pub fn change_node(&mut self, path: Path) -> Result<(), DustError> {
-        let (mut current, v) = match path.clone() {
-            Path::Absolute(v) => (self.head.clone(), v),
-            Path::Relative(v) => (self.current.clone(), v),
+        let path = self.absolute(&path);
+        resolve(&self.tree.nodes(), &path)?;
+        self.current = path;
+        Ok(())
+    }
+
+    /// Removes the node at `path` from the tree, like `rm`. A node with bindings or
+    /// children is only removed if `recursive` is set. The root, the current node and
+    /// its ancestors cannot be removed. The principal needs delete permission on
+    /// every node removed. The detached subtree is returned intact, out of reach until
+    /// it is put back with `restore_node`.
+    ///
+    /// Other cursors whose current node is removed cannot use it from then on,
+    /// until they change node.
+    pub fn delete_node(&self, path: Path, recursive: bool) -> Result<DeletedNode, DustError> {
+        let absolute = self.absolute(&path);
+        deletable(&self.current, &absolute, &path)?;
+
+        let mut storage = self.tree.lock()?;
+        let deleted = {
+            let head = self.tree.nodes();
+            let node = resolve(&head, &absolute)?;
+
+            if !recursive && !node.is_empty() {
+                return Err(DustError::SymbolTableError(format!("Node not empty: {}", path)));
+            }
+
+            check_subtree(&head, &self.principal, node, Permissions::DELETE)?;
+
+            let parent = absolute.parent().unwrap_or_else(Path::root);
+            DeletedNode {
+                node: node.clone(),
+                parent: resolve(&head, &parent)?.id(),
+            }
         };
 
-        for key in v.iter() {
-            let child = current.borrow().get_child(key.as_str())
-                .and_then(|child| Some(child.upgrade().unwrap()));
+        self.tree.commit(&mut storage, Operation::DeleteNode(absolute))?;
+        Ok(deleted)
+    }
+
+    /// Puts a subtree returned by `delete_node` back where it was removed from.
+    /// Fails if its parent node is no longer in the tree or its name has been reused.
+    /// The principal needs create-child permission on the parent.
+    pub fn restore_node(&self, node: DeletedNode) -> Result<(), DustError> {
+        let DeletedNode { node, parent } = node;
+        let path = node.path().clone();
+        let parent_path = path.parent().unwrap_or_else(Path::root);
+        let name = path.file_name().unwrap_or_default().to_string();
+
+        let mut storage = self.tree.lock()?;
 
-            match child {
-                Some(child) => current = child,
-                None => return Err(DustError::SymbolTableError(format!("Path not found: {:?}", path))),
+        let contents = {
+            let head = self.tree.nodes();
+
+            // the parent may have been deleted itself, and another made in its place
+            let attached = match head.node(&parent_path) {
+                Some(attached) if attached.id() == parent => attached,
+                _ => return Err(DustError::SymbolTableError(format!("Parent no longer exists: {}", path))),
+            };
+
+            if attached.get_child(&name).is_some() {
+                return Err(DustError::SymbolTableError(format!("Node already exists: {}", path)));
             }
+
+            check(&head, &self.principal, &parent_path, Permissions::CREATE_CHILD)?;
+
+            let mut contents = vec![];
+            node.visit(&mut |node| node.contents(&mut contents));
+            contents
+        };
+
+        let revision = self.tree.revision() + 1;
+        let batch = Operation::Batch(contents);
+        storage.append(revision, &batch)?;
+
+        // the restored subtree appears to watchers as if built anew, and its keys
+        // are set again at this revision
+        let events = match &batch {
+            Operation::Batch(contents) => contents
+                .iter()
+                .filter_map(|operation| match operation {
+                    Operation::MakeNode(path) => Some(Event::NodeCreated(path.clone())),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        };
+        let mut changes = Changes {
+            events,
+            ..Changes::default()
+        };
+        {
+            let mut head = write(&self.tree.head);
+            head.node_mut(&parent_path)
+                .ok_or_else(|| DustError::SymbolTableError(format!("Parent no longer exists: {}", path)))?
+                .insert_child(&name, node)?;
+            self.tree.apply(&mut head, revision, &batch, &mut changes);
+        }
+        self.tree.compact(&mut storage);
+        self.tree.notify(&changes);
+        Ok(())
+    }
+
+    /// The child nodes and bound keys of the node at `path`. The principal needs
+    /// read permission on it, which is checked before whether it exists.
+    pub fn list(&self, path: Path) -> Result<Vec<Entry>, DustError> {
+        let path = self.absolute(&path);
+        let head = self.tree.nodes();
+        check(&head, &self.principal, &path, Permissions::READ)?;
+        let node = resolve(&head, &path)?;
+        Ok(node.list())
+    }
+
+    /// Iterates depth-first over every entry below the node at `path`, leaving out
+    /// the entries of nodes the principal may not read. The walk shows the tree as
+    /// it was when it began; changes made meanwhile are not seen.
+    pub fn walk(&self, path: Path) -> Result<Walk, DustError> {
+        let path = self.absolute(&path);
+        let head = self.tree.nodes();
+        check(&head, &self.principal, &path, Permissions::READ)?;
+        let node = resolve(&head, &path)?;
+        Ok(Walk::readable_by(node, inherited_acl(&head, &path), &self.principal))
+    }
+
+    /// The paths of all nodes matching `pattern`, in depth-first order, among those
+    /// the principal may see listed. A relative pattern is resolved against the
+    /// current node. See `Path::matches`.
+    pub fn glob(&self, pattern: Path) -> Vec<Path> {
+        let pattern = self.absolute(&pattern);
+        let head = self.tree.nodes();
+
+        std::iter::once(Path::root())
+            .chain(
+                Walk::readable_by(&head, head.acl(), &self.principal).filter_map(|(path, entry)| match entry {
+                    Entry::Node(name) => Some(path + name.as_str()),
+                    Entry::Key(_, _) => None,
+                }),
+            )
+            .filter(|path| path.matches(&pattern))
+            .collect()
+    }
+
+    /// A copy of the value of `key` in the current node, or failing that, in its
+    /// nearest ancestor that binds it. The principal needs read permission on the
+    /// current node and the node `key` is found in. Values change only through `set`.
+    pub fn get(&self, key: &str) -> Result<Option<Object>, DustError> {
+        self.lookup(&self.current, key)
+    }
+
+    /// Looks `key` up in the node at the absolute `path`, then in its ancestors.
+    fn lookup(&self, path: &Path, key: &str) -> Result<Option<Object>, DustError> {
+        let head = self.tree.nodes();
+        check(&head, &self.principal, path, Permissions::READ)?;
+        resolve(&head, path)?;
+
+        match head.find(path, key) {
+            Some((found, value)) => {
+                check(&head, &self.principal, &found, Permissions::READ)?;
+                Ok(Some(value))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, key: &str, value: Object) -> Result<(), DustError> {
+        let mut storage = self.tree.lock()?;
+        {
+            let head = self.tree.nodes();
+            resolve(&head, &self.current)?;
+            check(&head, &self.principal, &self.current, Permissions::WRITE)?;
+        }
+        self.tree.commit(&mut storage, Operation::Set(self.current.clone(), key.to_string(), value))?;
+        Ok(())
+    }
+
+    /// Unbinds `key` in the current node, returning the value it was bound to.
+    pub fn remove(&self, key: &str) -> Result<Option<Object>, DustError> {
+        let mut storage = self.tree.lock()?;
+
+        let value = {
+            let head = self.tree.nodes();
+            let node = resolve(&head, &self.current)?;
+            check(&head, &self.principal, &self.current, Permissions::WRITE)?;
+            node.get_own(key)
+        };
+
+        let value = match value {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+
+        self.tree.commit(&mut storage, Operation::Remove(self.current.clone(), key.to_string()))?;
+        Ok(Some(value))
+    }
+
+    /// The tree's revision, which every change to it advances by one. A transaction
+    /// is a single change.
+    pub fn revision(&self) -> u64 {
+        self.tree.revision()
+    }
+
+    /// The current value of `key` in the current node, without looking in its
+    /// ancestors, and the revision that set it; revision 0 if it is not bound.
+    pub fn get_version(&self, key: &str) -> Result<Version, DustError> {
+        let head = self.tree.nodes();
+        check(&head, &self.principal, &self.current, Permissions::READ)?;
+        let node = resolve(&head, &self.current)?;
+        Ok(node.version(key))
+    }
+
+    /// The versions of `key` in the current node that are still kept, oldest first.
+    pub fn history(&self, key: &str) -> Result<Vec<Version>, DustError> {
+        let head = self.tree.nodes();
+        check(&head, &self.principal, &self.current, Permissions::READ)?;
+        let node = resolve(&head, &self.current)?;
+        Ok(node.history(key))
+    }
+
+    /// The value `key` had in the current node when the tree was at `revision`;
+    /// None if the key had not been bound yet. Fails if versions of the key that
+    /// far back have been dropped.
+    /// The history of a key goes with its node when the node is deleted.
+    pub fn get_at(&self, key: &str, revision: u64) -> Result<Option<Object>, DustError> {
+        if revision > self.tree.revision() {
+            return Err(DustError::RevisionError(format!("Revision {} is in the future", revision)));
+        }
+
+        let head = self.tree.nodes();
+        check(&head, &self.principal, &self.current, Permissions::READ)?;
+        let node = resolve(&head, &self.current)?;
+        node.get_at(key, revision)
+    }
+
+    /// Binds `key` in the current node to `value` if its current version is
+    /// `revision`, as returned by `get_version`; 0 means the key must not be bound.
+    /// Returns the tree's new revision.
+    pub fn compare_and_set(&self, key: &str, revision: u64, value: Object) -> Result<u64, DustError> {
+        let mut storage = self.tree.lock()?;
+        let current = {
+            let head = self.tree.nodes();
+            let node = resolve(&head, &self.current)?;
+            check(&head, &self.principal, &self.current, Permissions::WRITE)?;
+            node.version(key).revision
+        };
+
+        if current != revision {
+            return Err(DustError::RevisionError(format!(
+                "{} in {} is at revision {}, not {}",
+                key, self.current, current, revision
+            )));
         }
 
-        self.current = current;
+        self.tree.commit(&mut storage, Operation::Set(self.current.clone(), key.to_string(), value))
+    }
+
+    /// Gives the node at `path` `acl`, or with None, has it inherit its parent's.
+    /// Only the root principal can change ACLs.
+    pub fn set_acl(&self, path: Path, acl: Option<Acl>) -> Result<(), DustError> {
+        let path = self.absolute(&path);
+
+        if self.principal != Principal::Root {
+            return Err(DustError::PermissionError(format!(
+                "{} cannot change the ACL of {}",
+                self.principal, path
+            )));
+        }
+
+        let mut storage = self.tree.lock()?;
+        resolve(&self.tree.nodes(), &path)?;
+        self.tree.commit(&mut storage, Operation::SetAcl(path, acl))?;
         Ok(())
     }
 
-    pub fn delete_node(&mut self, path: Path) {
-        unimplemented!("delete")
+    /// The ACL given to the node at `path` itself; None if it inherits its parent's.
+    pub fn acl(&self, path: Path) -> Result<Option<Acl>, DustError> {
+        let path = self.absolute(&path);
+        let head = self.tree.nodes();
+        check(&head, &self.principal, &path, Permissions::READ)?;
+        let node = resolve(&head, &path)?;
+        Ok(node.acl().cloned())
+    }
+
+    /// Calls `callback` with each change made at or below `path`, and when a node
+    /// above it is deleted, until the returned Subscription is dropped. `path` need
+    /// not exist yet. The principal needs read permission on `path`, and only hears
+    /// of changes to nodes it may read.
+    ///
+    /// Callbacks run on the thread making the change, in the order changes are made,
+    /// and further changes wait for them. A change a callback makes to the tree
+    /// fails rather than wait for the change that called it.
+    pub fn watch(
+        &self,
+        path: Path,
+        callback: impl Fn(&Event) + Send + Sync + 'static,
+    ) -> Result<Subscription, DustError> {
+        let path = self.absolute(&path);
+        check(&self.tree.nodes(), &self.principal, &path, Permissions::READ)?;
+        Ok(self.tree.watchers.watch(path, self.principal.clone(), Arc::new(callback)))
+    }
+
+    /// Like `watch`, but the events are queued in a Stream, which is closed if it
+    /// falls too far behind; see `EventStream`.
+    pub fn watch_stream(&self, path: Path) -> Result<EventStream, DustError> {
+        let path = self.absolute(&path);
+        check(&self.tree.nodes(), &self.principal, &path, Permissions::READ)?;
+        Ok(self.tree.watchers.stream(path, self.principal.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn absolute(path: &[&str]) -> Path {
+        Path::Absolute(path.iter().map(|s| s.to_string()).collect())
+    }
+
+    fn relative(path: &[&str]) -> Path {
+        Path::Relative(path.iter().map(|s| s.to_string()).collect())
+    }
+
+    fn get(dust: &Dust, key: &str) -> Option<Object> {
+        dust.get(key).unwrap()
+    }
+
+    #[test]
+    fn test_make_node_then_change_node() {
+        let mut dust = Dust::new();
+
+        let node = dust.make_node(relative(&["a", "b", "c"])).unwrap();
+        assert_eq!(node, absolute(&["a", "b", "c"]));
+
+        dust.change_node(absolute(&["a", "b", "c"])).unwrap();
+        dust.change_node(absolute(&["a"])).unwrap();
+        dust.change_node(relative(&["b"])).unwrap();
+        assert!(dust.change_node(relative(&["x"])).is_err());
+    }
+
+    #[test]
+    fn test_make_node_keeps_existing_nodes() {
+        let mut dust = Dust::new();
+
+        dust.make_node(absolute(&["a"])).unwrap();
+        dust.change_node(absolute(&["a"])).unwrap();
+        dust.set("x", Object::Number(1.0)).unwrap();
+
+        dust.make_node(absolute(&["a", "b"])).unwrap();
+        dust.change_node(absolute(&["a"])).unwrap();
+        assert_eq!(get(&dust, "x"), Some(Object::Number(1.0)));
+
+        // making a node that is there already is not a change
+        let revision = dust.revision();
+        dust.make_node(absolute(&["a", "b"])).unwrap();
+        assert_eq!(dust.revision(), revision);
+    }
+
+    #[test]
+    fn test_get_and_set_through_levels() {
+        let mut dust = Dust::new();
+        dust.set("root", Object::Boolean(true)).unwrap();
+
+        dust.make_node(absolute(&["a"])).unwrap();
+        dust.change_node(absolute(&["a"])).unwrap();
+        dust.set("x", Object::Number(1.0)).unwrap();
+
+        dust.make_node(relative(&["b", "c"])).unwrap();
+        dust.change_node(relative(&["b", "c"])).unwrap();
+        dust.set("y", Object::String("deep".to_string())).unwrap();
+
+        // lookups fall back to ancestors
+        assert_eq!(get(&dust, "y"), Some(Object::String("deep".to_string())));
+        assert_eq!(get(&dust, "x"), Some(Object::Number(1.0)));
+        assert_eq!(get(&dust, "root"), Some(Object::Boolean(true)));
+
+        // and bindings stay in the node they were set in
+        dust.change_node(absolute(&["a"])).unwrap();
+        assert_eq!(get(&dust, "y"), None);
+        dust.change_node(absolute(&["a", "b", "c"])).unwrap();
+        assert_eq!(get(&dust, "y"), Some(Object::String("deep".to_string())));
+    }
+
+    #[test]
+    fn test_delete_node() {
+        let mut dust = Dust::new();
+        dust.make_node(absolute(&["a", "b"])).unwrap();
+        dust.make_node(absolute(&["a", "c"])).unwrap();
+
+        dust.delete_node(absolute(&["a", "b"]), false).unwrap();
+        assert!(dust.change_node(absolute(&["a", "b"])).is_err());
+        dust.change_node(absolute(&["a", "c"])).unwrap();
+        assert!(dust.delete_node(absolute(&["a", "b"]), false).is_err());
+    }
+
+    #[test]
+    fn test_delete_non_empty_node() {
+        let mut dust = Dust::new();
+        dust.make_node(absolute(&["a", "b"])).unwrap();
+        dust.make_node(absolute(&["x"])).unwrap();
+        dust.change_node(absolute(&["x"])).unwrap();
+        dust.set("k", Object::Null).unwrap();
+        dust.change_node(absolute(&[])).unwrap();
+
+        // children and bindings both count as content
+        assert!(dust.delete_node(absolute(&["a"]), false).is_err());
+        assert!(dust.delete_node(absolute(&["x"]), false).is_err());
+
+        let removed = dust.delete_node(absolute(&["a"]), true).unwrap();
+        assert_eq!(removed.path(), absolute(&["a"]));
+        assert!(dust.change_node(absolute(&["a", "b"])).is_err());
+        dust.delete_node(absolute(&["x"]), true).unwrap();
+
+        // the removed subtree is intact
+        dust.restore_node(removed).unwrap();
+        dust.change_node(absolute(&["a", "b"])).unwrap();
+    }
+
+    #[test]
+    fn test_delete_refuses_root_and_current_ancestors() {
+        let mut dust = Dust::new();
+        dust.make_node(absolute(&["a", "b", "c"])).unwrap();
+        dust.change_node(absolute(&["a", "b"])).unwrap();
+
+        assert!(dust.delete_node(absolute(&[]), true).is_err());
+        assert!(dust.delete_node(absolute(&["a"]), true).is_err());
+        assert!(dust.delete_node(absolute(&["a", "b"]), true).is_err());
+        dust.delete_node(relative(&["c"]), true).unwrap();
+    }
+
+    #[test]
+    fn test_restore_deleted_node() {
+        let mut dust = Dust::new();
+        dust.make_node(absolute(&["a", "b"])).unwrap();
+        dust.change_node(absolute(&["a", "b"])).unwrap();
+        dust.set("k", Object::Char('v')).unwrap();
+        dust.change_node(absolute(&[])).unwrap();
+
+        let removed = dust.delete_node(absolute(&["a"]), true).unwrap();
+        dust.restore_node(removed.clone()).unwrap();
+        assert!(dust.restore_node(removed).is_err(), "the name is taken again");
+
+        dust.change_node(absolute(&["a", "b"])).unwrap();
+        assert_eq!(get(&dust, "k"), Some(Object::Char('v')));
+    }
+
+    #[test]
+    fn test_dot_and_dot_dot_paths() {
+        let mut dust = Dust::new();
+        dust.make_node("/a/b/c".parse().unwrap()).unwrap();
+        dust.make_node("/a/./x/../d".parse().unwrap()).unwrap();
+
+        dust.change_node("/a/b/c".parse().unwrap()).unwrap();
+        assert_eq!(dust.current().to_string(), "/a/b/c");
+
+        dust.change_node("../../d".parse().unwrap()).unwrap();
+        assert_eq!(dust.current().to_string(), "/a/d");
+
+        dust.make_node("../e".parse().unwrap()).unwrap();
+        dust.change_node("./../e/.".parse().unwrap()).unwrap();
+        assert_eq!(dust.current().to_string(), "/a/e");
+
+        dust.change_node("../../../..".parse().unwrap()).unwrap();
+        assert_eq!(dust.current(), &Path::root());
+        assert!(dust.change_node("/a/x".parse().unwrap()).is_err());
+    }
+
+    fn build() -> Dust {
+        let mut dust = Dust::new();
+        dust.make_node("/services/api/config".parse().unwrap()).unwrap();
+        dust.make_node("/services/db/config".parse().unwrap()).unwrap();
+        dust.make_node("/services/db/replica".parse().unwrap()).unwrap();
+        dust.make_node("/users".parse().unwrap()).unwrap();
+        dust.change_node("/services/api".parse().unwrap()).unwrap();
+        dust.set("port", Object::Number(8080.0)).unwrap();
+        dust.set("name", Object::String("api".to_string())).unwrap();
+        dust.change_node("/".parse().unwrap()).unwrap();
+        dust
+    }
+
+    #[test]
+    fn test_list() {
+        let dust = build();
+
+        assert_eq!(dust.list("/services/api".parse().unwrap()).unwrap(), vec![
+            Entry::Node("config".to_string()),
+            Entry::Key("name".to_string(), ObjectKind::String),
+            Entry::Key("port".to_string(), ObjectKind::Number),
+        ]);
+        assert_eq!(dust.list("/".parse().unwrap()).unwrap(), vec![
+            Entry::Node("services".to_string()),
+            Entry::Node("users".to_string()),
+        ]);
+        assert!(dust.list("/nope".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_walk() {
+        let dust = build();
+
+        let walked: Vec<String> = dust
+            .walk("/services".parse().unwrap())
+            .unwrap()
+            .map(|(path, entry)| match entry {
+                Entry::Node(name) => format!("{}", path + name.as_str()),
+                Entry::Key(name, _) => format!("{} {}", path, name),
+            })
+            .collect();
+
+        assert_eq!(walked, vec![
+            "/services/api",
+            "/services/api/config",
+            "/services/api name",
+            "/services/api port",
+            "/services/db",
+            "/services/db/config",
+            "/services/db/replica",
+        ]);
+    }
+
+    #[test]
+    fn test_glob() {
+        let mut dust = build();
+
+        assert_eq!(dust.glob("/services/*/config".parse().unwrap()), vec![
+            "/services/api/config".parse::<Path>().unwrap(),
+            "/services/db/config".parse().unwrap(),
+        ]);
+        assert_eq!(dust.glob("/**/r*".parse().unwrap()), vec![
+            "/services/db/replica".parse::<Path>().unwrap(),
+        ]);
+        assert_eq!(dust.glob("/".parse().unwrap()), vec![Path::root()]);
+
+        dust.change_node("/services".parse().unwrap()).unwrap();
+        assert_eq!(dust.glob("d?".parse().unwrap()), vec!["/services/db".parse::<Path>().unwrap()]);
+    }
+
+    #[test]
+    fn test_reopen_from_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let mut dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        dust.make_node("/services/api/config".parse().unwrap()).unwrap();
+        dust.make_node("/services/db".parse().unwrap()).unwrap();
+        dust.make_node("/tmp/x".parse().unwrap()).unwrap();
+        dust.change_node("/services/api".parse().unwrap()).unwrap();
+        dust.set("port", Object::Number(8080.0)).unwrap();
+        dust.set("port", Object::Number(8081.0)).unwrap();
+        dust.change_node("/".parse().unwrap()).unwrap();
+        let db = dust.delete_node("/services/db".parse().unwrap(), false).unwrap();
+        dust.delete_node("/tmp".parse().unwrap(), true).unwrap();
+        dust.restore_node(db).unwrap();
+        let before: Vec<_> = dust.walk(Path::root()).unwrap().collect();
+        drop(dust);
+
+        let mut dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        assert_eq!(dust.walk(Path::root()).unwrap().collect::<Vec<_>>(), before);
+        dust.change_node("/services/api".parse().unwrap()).unwrap();
+        assert_eq!(get(&dust, "port"), Some(Object::Number(8081.0)));
+        assert!(dust.change_node("/tmp".parse().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_compaction_keeps_the_tree() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let mut dust = Dust::open(FileStorage::open(&log).unwrap().with_compaction_threshold(8)).unwrap();
+        dust.make_node("/a".parse().unwrap()).unwrap();
+        dust.change_node("/a".parse().unwrap()).unwrap();
+        for i in 0..100 {
+            dust.set("i", Object::Number(i as f64)).unwrap();
+        }
+        drop(dust);
+
+        // a hundred overwrites of one key compact down to a few records
+        assert!(std::fs::metadata(&log).unwrap().len() < 2048);
+
+        let mut dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        dust.change_node("/a".parse().unwrap()).unwrap();
+        assert_eq!(get(&dust, "i"), Some(Object::Number(99.0)));
     }
 
-    pub fn get(&self, key: &str) -> Option<Rc<RefCell<Object>>> {
-        self.current.borrow().get(key)
+    #[test]
+    fn test_restore_under_deleted_parent() {
+        let dust = Dust::new();
+        dust.make_node("/a/b".parse().unwrap()).unwrap();
+
+        let b = dust.delete_node("/a/b".parse().unwrap(), false).unwrap();
+        let _a = dust.delete_node("/a".parse().unwrap(), false).unwrap();
+        assert!(dust.restore_node(b).is_err());
     }
 
-    pub fn set(&mut self, key: &str, value: Object) {
-        self.current.borrow_mut().set(key, Rc::new(RefCell::new(value)));
+    #[test]
+    fn test_cursors_have_their_own_current_node() {
+        let mut dust = build();
+        let mut other = dust.cursor();
+
+        dust.change_node("/services/api".parse().unwrap()).unwrap();
+        other.change_node("/services/db".parse().unwrap()).unwrap();
+        assert_eq!(dust.current().to_string(), "/services/api");
+        assert_eq!(get(&dust, "port"), Some(Object::Number(8080.0)));
+        assert_eq!(get(&other, "port"), None);
+
+        // changes through one cursor are seen through the other
+        other.set("port", Object::Number(5432.0)).unwrap();
+        other.make_node("replica/lag".parse().unwrap()).unwrap();
+        assert!(dust.list("/services/db/replica".parse().unwrap()).unwrap().contains(&Entry::Node("lag".to_string())));
+
+        // a cursor's current node can be deleted through another cursor
+        dust.delete_node("/services/db".parse().unwrap(), true).unwrap();
+        assert!(other.get("port").is_err());
+        assert!(other.set("port", Object::Null).is_err());
+        other.change_node("/services/api".parse().unwrap()).unwrap();
+        assert_eq!(get(&other, "port"), Some(Object::Number(8080.0)));
+    }
+
+    #[test]
+    fn test_dust_is_send_and_sync() {
+        fn send_and_sync<T: Send + Sync>() {}
+        send_and_sync::<Dust>();
+    }
+
+    #[test]
+    fn test_concurrent_readers_and_writers() {
+        const WRITERS: usize = 8;
+        const NODES: usize = 60;
+
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap().with_compaction_threshold(64)).unwrap();
+        dust.make_node("/counter".parse().unwrap()).unwrap();
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|writer| {
+                    let mut dust = dust.cursor();
+                    scope.spawn(move || {
+                        let base: Path = format!("/w{}", writer).parse().unwrap();
+                        dust.make_node(base.clone()).unwrap();
+
+                        for i in 0..NODES {
+                            dust.change_node(base.clone()).unwrap();
+                            dust.make_node(relative(&[&format!("n{}", i)])).unwrap();
+                            dust.change_node(relative(&[&format!("n{}", i)])).unwrap();
+                            dust.set("i", Object::Number(i as f64)).unwrap();
+                            dust.change_node(base.clone()).unwrap();
+                            if i % 3 == 0 {
+                                dust.delete_node(relative(&[&format!("n{}", i)]), true).unwrap();
+                            }
+
+                            // increments are lost unless transactions are serialized
+                            dust.transaction(|tx| {
+                                let count = match tx.get("/counter".parse().unwrap(), "count")? {
+                                    Some(Object::Number(count)) => count,
+                                    _ => 0.0,
+                                };
+                                tx.set("/counter".parse().unwrap(), "count", Object::Number(count + 1.0))
+                            })
+                            .unwrap();
+                        }
+                    })
+                })
+                .collect();
+
+            for _ in 0..2 {
+                let mut dust = dust.cursor();
+                let done = &done;
+                scope.spawn(move || {
+                    while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                        let keys: Vec<Path> = dust
+                            .walk(Path::root())
+                            .unwrap()
+                            .filter(|(_, entry)| entry.name() == "i")
+                            .map(|(path, _)| path)
+                            .collect();
+
+                        for path in keys {
+                            // the node may be gone by now, but never holds the wrong value
+                            if dust.change_node(path.clone()).is_ok() {
+                                let expected: f64 = path.file_name().unwrap()[1..].parse().unwrap();
+                                if let Ok(Some(value)) = dust.get("i") {
+                                    assert_eq!(value, Object::Number(expected));
+                                }
+                            }
+                        }
+
+                        std::thread::sleep(std::time::Duration::from_millis(1));
+                    }
+                });
+            }
+
+            writers.into_iter().for_each(|writer| writer.join().unwrap());
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+
+        let mut dust = dust;
+        dust.change_node("/counter".parse().unwrap()).unwrap();
+        assert_eq!(get(&dust, "count"), Some(Object::Number((WRITERS * NODES) as f64)));
+
+        for writer in 0..WRITERS {
+            let nodes = dust.list(format!("/w{}", writer).parse().unwrap()).unwrap();
+            assert_eq!(nodes.len(), (0..NODES).filter(|i| i % 3 != 0).count());
+        }
+
+        // the log holds the changes in the order they were made
+        let walked: Vec<_> = dust.walk(Path::root()).unwrap().collect();
+        drop(dust);
+        let reopened = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        assert_eq!(reopened.walk(Path::root()).unwrap().collect::<Vec<_>>(), walked);
+    }
+
+    #[test]
+    fn test_readers_see_transactions_whole() {
+        const BATCHES: usize = 1000;
+
+        let dust = Dust::new();
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            let reader = scope.spawn(|| {
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    // each transaction binds two keys
+                    assert_eq!(dust.list(Path::root()).unwrap().len() % 2, 0);
+                }
+            });
+
+            for i in 0..BATCHES {
+                dust.transaction(|tx| {
+                    tx.set(Path::root(), &format!("a{}", i), Object::Null)?;
+                    tx.set(Path::root(), &format!("b{}", i), Object::Null)
+                })
+                .unwrap();
+            }
+
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+            reader.join().unwrap();
+        });
+
+        assert_eq!(dust.list(Path::root()).unwrap().len(), 2 * BATCHES);
+    }
+
+    fn version(revision: u64, value: Option<Object>) -> Version {
+        Version { revision, value }
+    }
+
+    #[test]
+    fn test_revisions_and_history() {
+        let mut dust = Dust::new();
+        assert_eq!(dust.revision(), 0);
+
+        dust.make_node(absolute(&["a"])).unwrap();
+        dust.change_node(absolute(&["a"])).unwrap();
+        dust.set("x", Object::Number(1.0)).unwrap();
+        dust.set("x", Object::Number(2.0)).unwrap();
+        dust.make_node(absolute(&["b"])).unwrap();
+        dust.remove("x").unwrap();
+        dust.set("x", Object::Number(3.0)).unwrap();
+        assert_eq!(dust.revision(), 6);
+
+        assert_eq!(dust.history("x").unwrap(), vec![
+            version(2, Some(Object::Number(1.0))),
+            version(3, Some(Object::Number(2.0))),
+            version(5, None),
+            version(6, Some(Object::Number(3.0))),
+        ]);
+        assert_eq!(dust.get_version("x").unwrap(), version(6, Some(Object::Number(3.0))));
+        assert_eq!(dust.get_version("y").unwrap(), version(0, None));
+
+        assert_eq!(dust.get_at("x", 2).unwrap(), Some(Object::Number(1.0)));
+        assert_eq!(dust.get_at("x", 4).unwrap(), Some(Object::Number(2.0)));
+        assert_eq!(dust.get_at("x", 5).unwrap(), None);
+        assert_eq!(dust.get_at("x", 6).unwrap(), Some(Object::Number(3.0)));
+        assert_eq!(dust.get_at("y", 3).unwrap(), None);
+        assert_eq!(dust.get_at("x", 1).unwrap(), None);
+        assert!(matches!(dust.get_at("x", 7), Err(DustError::RevisionError(_))));
+
+        // a transaction is one revision
+        dust.transaction(|tx| {
+            tx.set(absolute(&["a"]), "x", Object::Number(4.0))?;
+            tx.set(absolute(&["b"]), "y", Object::Number(5.0))
+        })
+        .unwrap();
+        assert_eq!(dust.revision(), 7);
+        assert_eq!(dust.get_version("x").unwrap().revision, 7);
+    }
+
+    #[test]
+    fn test_history_is_bounded() {
+        let dust = Dust::new().with_history(3);
+
+        for i in 1..=5 {
+            dust.set("x", Object::Number(i as f64)).unwrap();
+        }
+
+        let revisions: Vec<u64> = dust.history("x").unwrap().iter().map(|version| version.revision).collect();
+        assert_eq!(revisions, vec![3, 4, 5]);
+        assert_eq!(dust.get_at("x", 3).unwrap(), Some(Object::Number(3.0)));
+        assert!(dust.get_at("x", 2).is_err());
+    }
+
+    #[test]
+    fn test_compare_and_set() {
+        let dust = Dust::new();
+
+        let created = dust.compare_and_set("x", 0, Object::Number(1.0)).unwrap();
+        assert!(matches!(dust.compare_and_set("x", 0, Object::Number(2.0)), Err(DustError::RevisionError(_))));
+
+        let updated = dust.compare_and_set("x", created, Object::Number(2.0)).unwrap();
+        assert!(dust.compare_and_set("x", created, Object::Number(3.0)).is_err());
+        assert_eq!(get(&dust, "x"), Some(Object::Number(2.0)));
+
+        dust.remove("x").unwrap();
+        assert!(dust.compare_and_set("x", updated, Object::Number(3.0)).is_err());
+        dust.compare_and_set("x", 0, Object::Number(3.0)).unwrap();
+    }
+
+    #[test]
+    fn test_compare_and_set_across_threads() {
+        const THREADS: usize = 4;
+        const INCREMENTS: usize = 25;
+
+        let dust = Dust::new();
+        dust.set("count", Object::Number(0.0)).unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..THREADS {
+                let dust = dust.cursor();
+                scope.spawn(move || {
+                    for _ in 0..INCREMENTS {
+                        // retry until nobody else got in between the read and the write
+                        loop {
+                            let current = dust.get_version("count").unwrap();
+                            let count = match current.value {
+                                Some(Object::Number(count)) => count,
+                                other => panic!("unexpected count {:?}", other),
+                            };
+
+                            match dust.compare_and_set("count", current.revision, Object::Number(count + 1.0)) {
+                                Ok(_) => break,
+                                Err(DustError::RevisionError(_)) => continue,
+                                Err(e) => panic!("{}", e),
+                            }
+                        }
+                    }
+                });
+            }
+        });
+
+        assert_eq!(get(&dust, "count"), Some(Object::Number((THREADS * INCREMENTS) as f64)));
+    }
+
+    #[test]
+    fn test_revisions_survive_reopening_and_compaction() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap().with_compaction_threshold(4)).unwrap().with_history(4);
+        for i in 1..=10 {
+            dust.set("x", Object::Number(i as f64)).unwrap();
+        }
+        dust.make_node(absolute(&["a"])).unwrap();
+        let history = dust.history("x").unwrap();
+        drop(dust);
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap().with_history(4);
+        assert_eq!(dust.revision(), 11);
+        assert_eq!(dust.history("x").unwrap(), history);
+        assert_eq!(dust.get_at("x", 8).unwrap(), Some(Object::Number(8.0)));
+        assert!(matches!(dust.get_at("x", 6), Err(DustError::RevisionError(_))));
+
+        dust.set("x", Object::Null).unwrap();
+        assert_eq!(dust.get_version("x").unwrap().revision, 12);
+    }
+
+    #[test]
+    fn test_removal_survives_compaction_as_oldest_version() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap().with_compaction_threshold(1)).unwrap().with_history(2);
+        dust.set("x", Object::Number(1.0)).unwrap();
+        dust.remove("x").unwrap();
+        dust.set("x", Object::Number(2.0)).unwrap();
+        dust.set("y", Object::Number(3.0)).unwrap();
+        let history = dust.history("x").unwrap();
+        assert_eq!(history, vec![version(2, None), version(3, Some(Object::Number(2.0)))]);
+        drop(dust);
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap().with_history(2);
+        assert_eq!(dust.history("x").unwrap(), history);
+        assert_eq!(dust.get_at("x", 2).unwrap(), None);
+        assert!(matches!(dust.get_at("x", 1), Err(DustError::RevisionError(_))));
+        assert_eq!(dust.get_at("y", 1).unwrap(), None);
+    }
+
+    fn denied<T>(result: Result<T, DustError>) -> bool {
+        matches!(result, Err(DustError::PermissionError(_)))
+    }
+
+    /// /shared readable by all and writable by alice, /shared/private readable
+    /// by alice alone, and /public with no ACL.
+    fn shared() -> (Dust, Dust, Dust) {
+        let dust = Dust::new();
+        dust.make_node(absolute(&["shared", "private"])).unwrap();
+        dust.make_node(absolute(&["public"])).unwrap();
+        dust.set_acl(absolute(&["shared"]), Some(Acl::new(Permissions::READ).grant("alice", Permissions::ALL)))
+            .unwrap();
+        dust.set_acl(
+            absolute(&["shared", "private"]),
+            Some(Acl::new(Permissions::NONE).grant("alice", Permissions::READ)),
+        )
+        .unwrap();
+
+        let alice = dust.cursor_as(Principal::user("alice")).unwrap();
+        let bob = dust.cursor_as(Principal::user("bob")).unwrap();
+        (dust, alice, bob)
+    }
+
+    #[test]
+    fn test_acls_are_inherited_unless_overridden() {
+        let (_dust, mut alice, mut bob) = shared();
+
+        alice.change_node(absolute(&["shared"])).unwrap();
+        alice.set("x", Object::Number(1.0)).unwrap();
+        alice.make_node(relative(&["more", "nodes"])).unwrap();
+        assert!(denied(bob.delete_node(absolute(&["shared", "more"]), true)));
+
+        bob.change_node(absolute(&["shared", "more", "nodes"])).unwrap();
+        assert_eq!(get(&bob, "x"), Some(Object::Number(1.0)));
+        assert!(denied(bob.set("x", Object::Null)));
+        assert!(denied(bob.make_node(relative(&["mine"]))));
+        assert!(denied(bob.make_node(absolute(&["shared", "more"]))), "even where the node exists");
+
+        // /shared/private overrides what it would inherit from /shared
+        alice.change_node(relative(&["private"])).unwrap();
+        assert_eq!(get(&alice, "x"), Some(Object::Number(1.0)));
+        assert!(denied(alice.set("x", Object::Null)));
+        assert!(denied(bob.list(absolute(&["shared", "private"]))));
+
+        // without any ACL on the way up, everyone may do everything
+        bob.change_node(absolute(&["public"])).unwrap();
+        bob.set("y", Object::Null).unwrap();
+        bob.make_node(relative(&["mine"])).unwrap();
+        bob.delete_node(relative(&["mine"]), false).unwrap();
+    }
+
+    #[test]
+    fn test_reads_are_checked() {
+        let (dust, alice, mut bob) = shared();
+        let mut root = dust.cursor();
+        root.change_node(absolute(&["shared", "private"])).unwrap();
+        root.set("secret", Object::Boolean(true)).unwrap();
+
+        bob.change_node(absolute(&["shared", "private"])).unwrap();
+        assert!(denied(bob.get("secret")));
+        assert!(denied(bob.get_version("secret")));
+        assert!(denied(bob.history("secret")));
+        assert!(denied(bob.get_at("secret", 1)));
+        assert!(denied(bob.walk(relative(&[]))));
+
+        // nor can what cannot be read be told apart from what is not there
+        for path in [relative(&[]), relative(&["missing"])] {
+            assert!(denied(bob.list(path.clone())));
+            assert!(denied(bob.walk(path.clone())));
+            assert!(denied(bob.acl(path)));
+        }
+
+        // walking and globbing leave out what is below nodes that cannot be read
+        let walked: Vec<Path> = bob.walk(Path::root()).unwrap().map(|(path, _)| path).collect();
+        assert!(!walked.contains(&absolute(&["shared", "private"])));
+        assert!(alice.walk(Path::root()).unwrap().any(|(_, entry)| entry.name() == "secret"));
+        assert!(!bob.glob("/shared/*".parse().unwrap()).is_empty());
+        root.make_node(relative(&["deeper"])).unwrap();
+        assert!(bob.glob("/shared/private/*".parse().unwrap()).is_empty());
+        assert_eq!(alice.glob("/shared/private/*".parse().unwrap()), vec![absolute(&["shared", "private", "deeper"])]);
+
+        // a key found in an ancestor needs that ancestor to be readable
+        root.set_acl(absolute(&["shared", "private", "deeper"]), Some(Acl::new(Permissions::READ))).unwrap();
+        bob.change_node(relative(&["deeper"])).unwrap();
+        assert_eq!(get(&bob, "missing"), None);
+        assert!(denied(bob.get("secret")));
+    }
+
+    #[test]
+    fn test_deleting_and_restoring_are_checked() {
+        let (dust, alice, bob) = shared();
+        dust.set_acl(
+            absolute(&["public"]),
+            Some(Acl::new(Permissions::ALL).grant("alice", Permissions::READ | Permissions::WRITE | Permissions::CREATE_CHILD)),
+        )
+        .unwrap();
+        bob.make_node(absolute(&["public", "a", "b"])).unwrap();
+
+        // deleting a subtree takes delete permission on every node in it
+        dust.set_acl(absolute(&["public", "a", "b"]), Some(Acl::new(Permissions::READ))).unwrap();
+        assert!(denied(bob.delete_node(absolute(&["public", "a"]), true)));
+        dust.set_acl(absolute(&["public", "a", "b"]), None).unwrap();
+        assert!(denied(alice.delete_node(absolute(&["public", "a"]), true)));
+        let a = bob.delete_node(absolute(&["public", "a"]), true).unwrap();
+
+        // restoring a node takes create-child permission on its parent
+        dust.set_acl(absolute(&["public"]), Some(Acl::new(Permissions::READ))).unwrap();
+        assert!(denied(bob.restore_node(a.clone())));
+        dust.set_acl(absolute(&["public"]), None).unwrap();
+        bob.restore_node(a).unwrap();
+        bob.list(absolute(&["public", "a", "b"])).unwrap();
+    }
+
+    #[test]
+    fn test_only_root_changes_acls_and_principals() {
+        let (dust, alice, _bob) = shared();
+
+        assert!(denied(alice.set_acl(absolute(&["shared"]), None)));
+        assert!(denied(alice.cursor_as(Principal::user("bob"))));
+        assert!(denied(alice.cursor_as(Principal::Root)));
+        assert_eq!(alice.cursor_as(Principal::user("alice")).unwrap().principal(), &Principal::user("alice"));
+        assert_eq!(alice.cursor().principal(), &Principal::user("alice"));
+
+        assert_eq!(alice.acl(absolute(&["shared", "private"])).unwrap(), Some(Acl::new(Permissions::NONE).grant("alice", Permissions::READ)));
+        assert_eq!(alice.acl(absolute(&["public"])).unwrap(), None);
+
+        // root may do anything, whatever the ACLs say
+        dust.set_acl(absolute(&["public"]), Some(Acl::new(Permissions::NONE))).unwrap();
+        let mut root = dust.cursor();
+        root.change_node(absolute(&["public"])).unwrap();
+        root.set("x", Object::Null).unwrap();
+        dust.delete_node(absolute(&["public"]), true).unwrap();
+    }
+
+    #[test]
+    fn test_acls_survive_reopening_and_compaction() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap().with_compaction_threshold(3)).unwrap();
+        dust.make_node(absolute(&["a", "b"])).unwrap();
+        dust.set_acl(absolute(&["a"]), Some(Acl::new(Permissions::READ))).unwrap();
+        dust.set_acl(absolute(&["a", "b"]), Some(Acl::new(Permissions::ALL))).unwrap();
+        dust.set_acl(absolute(&["a", "b"]), None).unwrap();
+        drop(dust);
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        assert_eq!(dust.acl(absolute(&["a"])).unwrap(), Some(Acl::new(Permissions::READ)));
+        assert_eq!(dust.acl(absolute(&["a", "b"])).unwrap(), None);
+
+        let bob = dust.cursor_as(Principal::user("bob")).unwrap();
+        assert!(denied(bob.make_node(absolute(&["a", "b", "c"]))));
     }
 }
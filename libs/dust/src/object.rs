@@ -1,3 +1,4 @@
+#[derive(Clone, Debug, PartialEq)]
 pub enum Object {
     Boolean(bool),
     Bytevector(Vec<u8>),
@@ -11,3 +12,34 @@ pub enum Object {
     Vector(Vec<Object>),
 }
 
+/// The variant of an Object, without its contents.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ObjectKind {
+    Boolean,
+    Bytevector,
+    Char,
+    Eof,
+    Null,
+    Number,
+    Pair,
+    String,
+    Symbol,
+    Vector,
+}
+
+impl Object {
+    pub fn kind(&self) -> ObjectKind {
+        match self {
+            Object::Boolean(_) => ObjectKind::Boolean,
+            Object::Bytevector(_) => ObjectKind::Bytevector,
+            Object::Char(_) => ObjectKind::Char,
+            Object::Eof => ObjectKind::Eof,
+            Object::Null => ObjectKind::Null,
+            Object::Number(_) => ObjectKind::Number,
+            Object::Pair(_, _) => ObjectKind::Pair,
+            Object::String(_) => ObjectKind::String,
+            Object::Symbol(_) => ObjectKind::Symbol,
+            Object::Vector(_) => ObjectKind::Vector,
+        }
+    }
+}
@@ -1,6 +1,11 @@
 use std::ops;
+use std::str::FromStr;
 
-#[derive(Clone, Debug)]
+use crate::DustError;
+
+/// A path in the Dust tree. The root is `Path::Absolute(vec![])`.
+/// Paths parsed from strings are normalized; see `normalize`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Path {
     Relative(Vec<String>),
     Absolute(Vec<String>),
@@ -42,6 +47,8 @@ impl ops::Add<&Path> for Path {
     }
 }
 
+/// `a - b` is the relative path that leads from `b` to `a`, so that
+/// `b.join(&(a - b)) == a` for normalized paths of the same kind.
 impl ops::Sub<&Path> for Path {
     type Output = Path;
 
@@ -49,14 +56,44 @@ impl ops::Sub<&Path> for Path {
         let a = self.as_vector();
         let b = rhs.as_vector();
 
-        let c = a.iter().zip(b.iter()).skip_while(|(a, b)| a == b).map(|(a, _)| a).cloned().collect();
+        let common = a.iter().zip(b.iter()).take_while(|(a, b)| a == b).count();
+
+        let c = std::iter::repeat_n("..".to_string(), b.len() - common)
+            .chain(a[common..].iter().cloned())
+            .collect();
 
         Path::Relative(c)
     }
 }
 
+impl FromStr for Path {
+    type Err = DustError;
+
+    /// Parses `/a/b` as an absolute path and `a/b` as a relative one, normalizing
+    /// the result. Empty components are ignored, so `a//b` is `a/b`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(DustError::PathError("Empty path".to_string()));
+        }
+
+        let components = s.split('/').map(str::to_string).collect();
+
+        let path = if s.starts_with('/') {
+            Path::Absolute(components)
+        } else {
+            Path::Relative(components)
+        };
+
+        Ok(path.normalize())
+    }
+}
+
 impl Path {
-    fn includes(&self, other: &Path) -> bool {
+    pub fn root() -> Path {
+        Path::Absolute(vec![])
+    }
+
+    pub fn includes(&self, other: &Path) -> bool {
         match (self, other) {
             (Path::Absolute(a), Path::Absolute(b)) => {
                 a.len() >= b.len() && a.iter().zip(b.iter()).all(|(a, b)| a == b)
@@ -74,11 +111,206 @@ impl Path {
             Path::Relative(v) => v.clone(),
         }
     }
+
+    fn components(&self) -> &[String] {
+        match self {
+            Path::Absolute(v) => v,
+            Path::Relative(v) => v,
+        }
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        matches!(self, Path::Absolute(_))
+    }
+
+    /// Removes empty and `.` components and resolves `..` against the component
+    /// before it. `..` at the root stays at the root; leading `..` components of a
+    /// relative path are kept.
+    pub fn normalize(&self) -> Path {
+        let mut v: Vec<String> = vec![];
+
+        for component in self.components() {
+            match component.as_str() {
+                "" | "." => {}
+                ".." => match v.last() {
+                    Some(last) if last != ".." => {
+                        v.pop();
+                    }
+                    _ if self.is_absolute() => {}
+                    _ => v.push(component.clone()),
+                },
+                _ => v.push(component.clone()),
+            }
+        }
+
+        match self {
+            Path::Absolute(_) => Path::Absolute(v),
+            Path::Relative(_) => Path::Relative(v),
+        }
+    }
+
+    /// The path without its last component, or None for the root and the empty relative path.
+    pub fn parent(&self) -> Option<Path> {
+        match self {
+            Path::Absolute(v) if !v.is_empty() => Some(Path::Absolute(v[..v.len() - 1].to_vec())),
+            Path::Relative(v) if !v.is_empty() => Some(Path::Relative(v[..v.len() - 1].to_vec())),
+            _ => None,
+        }
+    }
+
+    /// The last component, unless the path is empty or ends in `..`.
+    pub fn file_name(&self) -> Option<&str> {
+        self.components()
+            .last()
+            .map(String::as_str)
+            .filter(|name| *name != "..")
+    }
+
+    /// Resolves `other` against `self`: an absolute `other` replaces `self`,
+    /// a relative one is appended. The result is normalized.
+    pub fn join(&self, other: &Path) -> Path {
+        match other {
+            Path::Absolute(_) => other.normalize(),
+            Path::Relative(_) => (self.clone() + other).normalize(),
+        }
+    }
+
+    /// The relative path that remains after removing `base` from the front of `self`,
+    /// or None if `base` is not a prefix of `self`.
+    pub fn strip_prefix(&self, base: &Path) -> Option<Path> {
+        self.includes(base)
+            .then(|| Path::Relative(self.components()[base.components().len()..].to_vec()))
+    }
+
+    /// Whether `self` matches the glob `pattern`, component by component. In a
+    /// pattern component `*` matches any run of characters and `?` any one
+    /// character; a `**` component matches any number of components.
+    /// Absolute paths only match absolute patterns, and relative ones relative patterns.
+    pub fn matches(&self, pattern: &Path) -> bool {
+        self.is_absolute() == pattern.is_absolute()
+            && matches_components(self.components(), pattern.components())
+    }
+}
+
+fn matches_components(path: &[String], pattern: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|skip| matches_components(&path[skip..], rest))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((component, path)) => {
+                let component: Vec<char> = component.chars().collect();
+                let first: Vec<char> = first.chars().collect();
+                matches_component(&component, &first) && matches_components(path, rest)
+            }
+            None => false,
+        },
+    }
+}
+
+fn matches_component(name: &[char], pattern: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| matches_component(&name[skip..], rest)),
+        Some((c, rest)) => match name.split_first() {
+            Some((n, name)) => (*c == '?' || c == n) && matches_component(name, rest),
+            None => false,
+        },
+    }
 }
 
 impl std::fmt::Display for Path {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let v = self.as_vector();
-        write!(f, "{}", v.join("/"))
+        match self {
+            Path::Absolute(v) => write!(f, "/{}", v.join("/")),
+            Path::Relative(v) if v.is_empty() => write!(f, "."),
+            Path::Relative(v) => write!(f, "{}", v.join("/")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(s: &str) -> Path {
+        s.parse().unwrap()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(path("/"), Path::root());
+        assert_eq!(path("/a/b/../c"), Path::Absolute(vec!["a".to_string(), "c".to_string()]));
+        assert_eq!(path("x/./y"), Path::Relative(vec!["x".to_string(), "y".to_string()]));
+        assert_eq!(path("a//b/"), path("a/b"));
+        assert_eq!(path("/.."), Path::root());
+        assert_eq!(path("../a/../../b"), Path::Relative(vec!["..".to_string(), "..".to_string(), "b".to_string()]));
+        assert_eq!(path("a/.."), Path::Relative(vec![]));
+        assert!("".parse::<Path>().is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Path::root().to_string(), "/");
+        assert_eq!(path("/a/b").to_string(), "/a/b");
+        assert_eq!(path("a/b").to_string(), "a/b");
+        assert_eq!(path("a/..").to_string(), ".");
+        assert_eq!(path(&path("/a/b/../c").to_string()), path("/a/c"));
+    }
+
+    #[test]
+    fn test_parent_and_file_name() {
+        assert_eq!(path("/a/b").parent(), Some(path("/a")));
+        assert_eq!(path("/a").parent(), Some(Path::root()));
+        assert_eq!(Path::root().parent(), None);
+        assert_eq!(path("a").parent(), Some(Path::Relative(vec![])));
+
+        assert_eq!(path("/a/b").file_name(), Some("b"));
+        assert_eq!(Path::root().file_name(), None);
+        assert_eq!(path("../..").file_name(), None);
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(path("/a/b").join(&path("c")), path("/a/b/c"));
+        assert_eq!(path("/a/b").join(&path("../c")), path("/a/c"));
+        assert_eq!(path("/a/b").join(&path("/c")), path("/c"));
+        assert_eq!(Path::root().join(&path("../../c")), path("/c"));
+        assert_eq!(path("a").join(&path("../../c")), path("../c"));
+    }
+
+    #[test]
+    fn test_strip_prefix() {
+        assert_eq!(path("/a/b/c").strip_prefix(&path("/a")), Some(path("b/c")));
+        assert_eq!(path("/a/b").strip_prefix(&path("/a/b")), Some(Path::Relative(vec![])));
+        assert_eq!(path("/a/b").strip_prefix(&Path::root()), Some(path("a/b")));
+        assert_eq!(path("/a/b").strip_prefix(&path("/b")), None);
+        assert_eq!(path("/a/b").strip_prefix(&path("a")), None);
+    }
+
+    #[test]
+    fn test_sub() {
+        assert_eq!(path("/a/b/c") - &path("/a"), path("b/c"));
+        assert_eq!(path("/a/b/c/d") - &path("/a/x"), path("../b/c/d"));
+        assert_eq!(path("/a") - &path("/a/b/c"), path("../.."));
+        assert_eq!(path("/a") - &path("/a"), Path::Relative(vec![]));
+
+        let (a, b) = (path("/a/b/c"), path("/a/x/y"));
+        assert_eq!(b.join(&(a.clone() - &b)), a);
+    }
+
+    #[test]
+    fn test_matches() {
+        assert!(path("/services/api/config").matches(&path("/services/*/config")));
+        assert!(!path("/services/api/v1/config").matches(&path("/services/*/config")));
+        assert!(!path("/services/config").matches(&path("/services/*/config")));
+        assert!(path("/services/api/v1/config").matches(&path("/services/**/config")));
+        assert!(path("/services/config").matches(&path("/services/**/config")));
+        assert!(path("/services/api-2").matches(&path("/services/api-?")));
+        assert!(path("/services/api").matches(&path("/ser*s/*a*i*")));
+        assert!(!path("/services/api").matches(&path("/services/b*")));
+        assert!(!path("services/api").matches(&path("/services/api")));
+        assert!(Path::root().matches(&path("/**")));
+    }
+}
@@ -0,0 +1,823 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::DustError;
+use crate::acl::{Acl, Permissions};
+use crate::object::Object;
+use crate::path::Path;
+
+/// A change to the Dust tree, as recorded by a Storage. Paths are absolute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Operation {
+    /// Creates the node and any missing ancestors.
+    MakeNode(Path),
+    /// Removes the node and everything below it.
+    DeleteNode(Path),
+    /// Binds a key in the node, creating the node if needed.
+    Set(Path, String, Object),
+    /// Unbinds a key in the node.
+    Remove(Path, String),
+    /// Gives the node its own ACL, or with None, has it inherit its parent's.
+    SetAcl(Path, Option<Acl>),
+    /// Marks the versions kept of a key in the node as not going back to its first.
+    /// Only found in snapshots, after the versions it applies to.
+    Trimmed(Path, String),
+    /// Operations that are recorded, and replayed, together or not at all.
+    Batch(Vec<Operation>),
+}
+
+/// Where a Dust tree records its changes so that it can be rebuilt later.
+/// Every change is appended, with the revision of the tree it produces, before it
+/// is applied in memory, and `Dust::open` replays whatever `load` returns.
+pub trait Storage: Send {
+    /// The operations recorded so far with their revisions, oldest first.
+    fn load(&mut self) -> Result<Vec<(u64, Operation)>, DustError>;
+
+    /// Durably records `operation`. Nothing is recorded if this fails.
+    fn append(&mut self, revision: u64, operation: &Operation) -> Result<(), DustError>;
+
+    /// Whether enough has been appended since the last compaction to make another worthwhile.
+    fn wants_compaction(&self) -> bool {
+        false
+    }
+
+    /// Replaces everything recorded with `snapshot`, which rebuilds the same tree.
+    fn compact(&mut self, snapshot: &[(u64, Operation)]) -> Result<(), DustError>;
+}
+
+/// Records nothing, so the tree lives only in memory. This is what `Dust::new` uses.
+#[derive(Debug, Default)]
+pub struct MemoryStorage;
+
+impl Storage for MemoryStorage {
+    fn load(&mut self) -> Result<Vec<(u64, Operation)>, DustError> {
+        Ok(vec![])
+    }
+
+    fn append(&mut self, _revision: u64, _operation: &Operation) -> Result<(), DustError> {
+        Ok(())
+    }
+
+    fn compact(&mut self, _snapshot: &[(u64, Operation)]) -> Result<(), DustError> {
+        Ok(())
+    }
+}
+
+const MAGIC: &[u8; 8] = b"DUSTLOG3";
+/// The length of a record's header: the payload's length and CRC-32, and a CRC-32 of both.
+const HEADER: usize = 12;
+const DEFAULT_COMPACTION_THRESHOLD: usize = 1024;
+
+/// Records operations in an append-only log file, synced after every record.
+///
+/// Each record is framed by its length and a CRC-32 of its contents, themselves
+/// covered by a CRC-32, so a record torn by a crash is detected when the log is
+/// loaded and cut off, and damage anywhere else is reported rather than mistaken
+/// for a torn record. Once enough
+/// records have accumulated the log is compacted: a snapshot of the tree is
+/// written to a temporary file which then atomically replaces the log.
+///
+/// Only one FileStorage at a time, in this process or any other, may have a log
+/// open: it holds an exclusive lock on a `.lock` file next to the log until dropped.
+pub struct FileStorage {
+    path: PathBuf,
+    file: File,
+    /// Locked for as long as the log is open here; never read or written.
+    _lock: File,
+    len: u64,
+    records: usize,
+    compacted: usize,
+    compaction_threshold: usize,
+    /// Set once a compaction has renamed its file over the log, until the new log
+    /// has been opened and the rename made durable. Appends wait for both.
+    replaced: bool,
+}
+
+impl FileStorage {
+    /// Opens the log at `path`, creating it if it does not exist. Fails if the log
+    /// is open in another FileStorage.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, DustError> {
+        let path = path.into();
+
+        let lock = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(lock_path(&path))
+            .map_err(storage_error)?;
+        match lock.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(DustError::StorageError(format!("Log in use elsewhere: {}", path.display())));
+            }
+            Err(TryLockError::Error(e)) => return Err(storage_error(e)),
+        }
+
+        // left behind by a crash during compaction, before it replaced the log
+        match fs::remove_file(compaction_path(&path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(storage_error(e)),
+            _ => {}
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)
+            .map_err(storage_error)?;
+
+        let mut header = vec![];
+        (&mut file).take(MAGIC.len() as u64).read_to_end(&mut header).map_err(storage_error)?;
+
+        if header.len() < MAGIC.len() && MAGIC.starts_with(&header) {
+            // a new log, or one whose creation was cut short
+            file.set_len(0).map_err(storage_error)?;
+            file.write_all(MAGIC).map_err(storage_error)?;
+            file.sync_all().map_err(storage_error)?;
+            sync_directory(&path)?;
+        } else if header != MAGIC {
+            return Err(DustError::StorageError(format!("Not a Dust log: {}", path.display())));
+        }
+
+        let len = file.metadata().map_err(storage_error)?.len();
+
+        Ok(FileStorage {
+            path,
+            file,
+            _lock: lock,
+            len,
+            records: 0,
+            compacted: 0,
+            compaction_threshold: DEFAULT_COMPACTION_THRESHOLD,
+            replaced: false,
+        })
+    }
+
+    /// Compacts the log once `threshold` records have been added to it since it was
+    /// last compacted. Records found when the log is loaded all count as added.
+    pub fn with_compaction_threshold(mut self, threshold: usize) -> Self {
+        self.compaction_threshold = threshold;
+        self
+    }
+
+    /// Opens the log a compaction put in place of the old one and makes the
+    /// replacement durable. Until this succeeds, `file` may be the unlinked old log.
+    fn finish_replacement(&mut self) -> Result<(), DustError> {
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path).map_err(storage_error)?;
+        sync_directory(&self.path)?;
+        self.replaced = false;
+        Ok(())
+    }
+}
+
+impl Storage for FileStorage {
+    /// Reads every record. A last record that is incomplete or fails its checksum is
+    /// what a crash mid-append leaves behind, and is truncated away. A damaged header,
+    /// or a damaged record with more of the log after it, cannot have been left by a
+    /// crash, and loading fails rather than dropping the records that follow it.
+    fn load(&mut self) -> Result<Vec<(u64, Operation)>, DustError> {
+        let mut bytes = vec![];
+        self.file.seek(SeekFrom::Start(0)).map_err(storage_error)?;
+        self.file.read_to_end(&mut bytes).map_err(storage_error)?;
+
+        let mut operations = vec![];
+        let mut offset = MAGIC.len();
+
+        loop {
+            match record_at(&bytes, offset) {
+                Record::Intact(payload) => {
+                    operations.push(Decoder::new(payload).record_to_end()?);
+                    offset += HEADER + payload.len();
+                }
+                Record::Torn => break,
+                Record::Corrupt => {
+                    return Err(DustError::StorageError(format!(
+                        "Corrupt record at offset {} of {}",
+                        offset,
+                        self.path.display()
+                    )));
+                }
+            }
+        }
+
+        if offset < bytes.len() {
+            self.file.set_len(offset as u64).map_err(storage_error)?;
+            self.file.sync_all().map_err(storage_error)?;
+        }
+
+        self.len = offset as u64;
+        self.records = operations.len();
+        self.compacted = 0;
+        Ok(operations)
+    }
+
+    fn append(&mut self, revision: u64, operation: &Operation) -> Result<(), DustError> {
+        if self.replaced {
+            self.finish_replacement()?;
+        }
+
+        let record = record(revision, operation);
+
+        let written = self.file.write_all(&record).and_then(|_| self.file.sync_data());
+
+        if let Err(e) = written {
+            // drop whatever part of the record made it to the file
+            let _ = self.file.set_len(self.len);
+            return Err(storage_error(e));
+        }
+
+        self.len += record.len() as u64;
+        self.records += 1;
+        Ok(())
+    }
+
+    fn wants_compaction(&self) -> bool {
+        self.records - self.compacted >= self.compaction_threshold
+    }
+
+    fn compact(&mut self, snapshot: &[(u64, Operation)]) -> Result<(), DustError> {
+        let compaction = compaction_path(&self.path);
+
+        let mut bytes = MAGIC.to_vec();
+        snapshot.iter().for_each(|(revision, operation)| bytes.extend(record(*revision, operation)));
+
+        let mut file = File::create(&compaction).map_err(storage_error)?;
+        file.write_all(&bytes).and_then(|_| file.sync_all()).map_err(storage_error)?;
+        fs::rename(&compaction, &self.path).map_err(storage_error)?;
+
+        // the snapshot is the log from here on, even if it cannot be opened yet
+        self.replaced = true;
+        self.len = bytes.len() as u64;
+        self.records = snapshot.len();
+        self.compacted = snapshot.len();
+        self.finish_replacement()
+    }
+}
+
+fn storage_error(e: std::io::Error) -> DustError {
+    DustError::StorageError(e.to_string())
+}
+
+fn compaction_path(path: &std::path::Path) -> PathBuf {
+    let mut compaction = path.as_os_str().to_owned();
+    compaction.push(".compact");
+    PathBuf::from(compaction)
+}
+
+fn lock_path(path: &std::path::Path) -> PathBuf {
+    let mut lock = path.as_os_str().to_owned();
+    lock.push(".lock");
+    PathBuf::from(lock)
+}
+
+/// Makes the creation or replacement of the file at `path` durable.
+fn sync_directory(path: &std::path::Path) -> Result<(), DustError> {
+    match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => {
+            File::open(directory).and_then(|directory| directory.sync_all()).map_err(storage_error)
+        }
+        _ => File::open(".").and_then(|directory| directory.sync_all()).map_err(storage_error),
+    }
+}
+
+/// Frames an encoded revision and operation as `length, crc32, header crc32,
+/// payload`, where the header CRC covers the length and payload CRC. Integers are
+/// little-endian.
+fn record(revision: u64, operation: &Operation) -> Vec<u8> {
+    let mut payload = revision.to_le_bytes().to_vec();
+    encode_operation(operation, &mut payload);
+
+    let mut record = Vec::with_capacity(payload.len() + HEADER);
+    record.extend((payload.len() as u32).to_le_bytes());
+    record.extend(crc32(&payload).to_le_bytes());
+    record.extend(crc32(&record).to_le_bytes());
+    record.extend(payload);
+    record
+}
+
+/// What a log holds at an offset.
+enum Record<'a> {
+    /// The payload of a complete record that passes its checksum.
+    Intact(&'a [u8]),
+    /// Nothing, or a last record that is incomplete or fails its checksum.
+    Torn,
+    /// A record whose header fails its checksum, or whose payload does with more
+    /// of the log after it.
+    Corrupt,
+}
+
+fn record_at(bytes: &[u8], offset: usize) -> Record<'_> {
+    let Some(header) = bytes.get(offset..offset + HEADER) else {
+        return Record::Torn;
+    };
+    let len = u32::from_le_bytes(header[0..4].try_into().unwrap()) as usize;
+    let crc = u32::from_le_bytes(header[4..8].try_into().unwrap());
+
+    // the length cannot be trusted to find the end of the record without this
+    if crc32(&header[0..8]) != u32::from_le_bytes(header[8..12].try_into().unwrap()) {
+        return match offset + HEADER == bytes.len() {
+            true => Record::Torn,
+            false => Record::Corrupt,
+        };
+    }
+
+    match bytes.get(offset + HEADER..offset + HEADER + len) {
+        None => Record::Torn,
+        Some(payload) if crc32(payload) == crc => Record::Intact(payload),
+        Some(_) if offset + HEADER + len == bytes.len() => Record::Torn,
+        Some(_) => Record::Corrupt,
+    }
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg()))
+    })
+}
+
+fn encode_operation(operation: &Operation, out: &mut Vec<u8>) {
+    match operation {
+        Operation::MakeNode(path) => {
+            out.push(0);
+            encode_path(path, out);
+        }
+        Operation::DeleteNode(path) => {
+            out.push(1);
+            encode_path(path, out);
+        }
+        Operation::Set(path, key, value) => {
+            out.push(2);
+            encode_path(path, out);
+            encode_bytes(key.as_bytes(), out);
+            encode_object(value, out);
+        }
+        Operation::Remove(path, key) => {
+            out.push(4);
+            encode_path(path, out);
+            encode_bytes(key.as_bytes(), out);
+        }
+        Operation::Batch(operations) => {
+            out.push(3);
+            out.extend((operations.len() as u32).to_le_bytes());
+            operations.iter().for_each(|operation| encode_operation(operation, out));
+        }
+        Operation::Trimmed(path, key) => {
+            out.push(6);
+            encode_path(path, out);
+            encode_bytes(key.as_bytes(), out);
+        }
+        Operation::SetAcl(path, acl) => {
+            out.push(5);
+            encode_path(path, out);
+            match acl {
+                None => out.push(0),
+                Some(acl) => {
+                    out.extend([1, acl.others().bits()]);
+                    out.extend((acl.users().count() as u32).to_le_bytes());
+                    for (user, permissions) in acl.users() {
+                        encode_bytes(user.as_bytes(), out);
+                        out.push(permissions.bits());
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn encode_path(path: &Path, out: &mut Vec<u8>) {
+    out.push(path.is_absolute() as u8);
+
+    let components = path.as_vector();
+    out.extend((components.len() as u32).to_le_bytes());
+    components.iter().for_each(|component| encode_bytes(component.as_bytes(), out));
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend((bytes.len() as u32).to_le_bytes());
+    out.extend(bytes);
+}
+
+fn encode_object(object: &Object, out: &mut Vec<u8>) {
+    match object {
+        Object::Boolean(b) => out.extend([0, *b as u8]),
+        Object::Bytevector(v) => {
+            out.push(1);
+            encode_bytes(v, out);
+        }
+        Object::Char(c) => {
+            out.push(2);
+            out.extend((*c as u32).to_le_bytes());
+        }
+        Object::Eof => out.push(3),
+        Object::Null => out.push(4),
+        Object::Number(n) => {
+            out.push(5);
+            out.extend(n.to_bits().to_le_bytes());
+        }
+        Object::Pair(car, cdr) => {
+            out.push(6);
+            encode_object(car, out);
+            encode_object(cdr, out);
+        }
+        Object::String(s) => {
+            out.push(7);
+            encode_bytes(s.as_bytes(), out);
+        }
+        Object::Symbol(s) => {
+            out.push(8);
+            encode_bytes(s.as_bytes(), out);
+        }
+        Object::Vector(v) => {
+            out.push(9);
+            out.extend((v.len() as u32).to_le_bytes());
+            v.iter().for_each(|object| encode_object(object, out));
+        }
+    }
+}
+
+/// Reads back what the encode functions wrote. Records are checksummed, so a
+/// payload that does not decode means the log was written by something else.
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    fn corrupt() -> DustError {
+        DustError::StorageError("Corrupt record in Dust log".to_string())
+    }
+
+    fn record_to_end(&mut self) -> Result<(u64, Operation), DustError> {
+        let record = (self.u64()?, self.operation()?);
+
+        match self.bytes.is_empty() {
+            true => Ok(record),
+            false => Err(Self::corrupt()),
+        }
+    }
+
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DustError> {
+        if self.bytes.len() < n {
+            return Err(Self::corrupt());
+        }
+
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, DustError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, DustError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, DustError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DustError> {
+        let len = self.u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn string(&mut self) -> Result<String, DustError> {
+        String::from_utf8(self.bytes()?).map_err(|_| Self::corrupt())
+    }
+
+    fn path(&mut self) -> Result<Path, DustError> {
+        let absolute = self.u8()?;
+        let components = (0..self.u32()?).map(|_| self.string()).collect::<Result<_, _>>()?;
+
+        match absolute {
+            0 => Ok(Path::Relative(components)),
+            1 => Ok(Path::Absolute(components)),
+            _ => Err(Self::corrupt()),
+        }
+    }
+
+    fn operation(&mut self) -> Result<Operation, DustError> {
+        match self.u8()? {
+            0 => Ok(Operation::MakeNode(self.path()?)),
+            1 => Ok(Operation::DeleteNode(self.path()?)),
+            2 => Ok(Operation::Set(self.path()?, self.string()?, self.object()?)),
+            3 => {
+                let operations = (0..self.u32()?).map(|_| self.operation()).collect::<Result<_, _>>()?;
+                Ok(Operation::Batch(operations))
+            }
+            4 => Ok(Operation::Remove(self.path()?, self.string()?)),
+            5 => Ok(Operation::SetAcl(self.path()?, self.acl()?)),
+            6 => Ok(Operation::Trimmed(self.path()?, self.string()?)),
+            _ => Err(Self::corrupt()),
+        }
+    }
+
+    fn acl(&mut self) -> Result<Option<Acl>, DustError> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => {
+                let mut acl = Acl::new(self.permissions()?);
+                for _ in 0..self.u32()? {
+                    acl = acl.grant(&self.string()?, self.permissions()?);
+                }
+                Ok(Some(acl))
+            }
+            _ => Err(Self::corrupt()),
+        }
+    }
+
+    fn permissions(&mut self) -> Result<Permissions, DustError> {
+        Permissions::from_bits(self.u8()?).ok_or_else(Self::corrupt)
+    }
+
+    fn object(&mut self) -> Result<Object, DustError> {
+        match self.u8()? {
+            0 => Ok(Object::Boolean(self.u8()? != 0)),
+            1 => Ok(Object::Bytevector(self.bytes()?)),
+            2 => char::from_u32(self.u32()?).map(Object::Char).ok_or_else(Self::corrupt),
+            3 => Ok(Object::Eof),
+            4 => Ok(Object::Null),
+            5 => Ok(Object::Number(f64::from_bits(self.u64()?))),
+            6 => Ok(Object::Pair(Box::new(self.object()?), Box::new(self.object()?))),
+            7 => Ok(Object::String(self.string()?)),
+            8 => Ok(Object::Symbol(self.string()?)),
+            9 => Ok(Object::Vector((0..self.u32()?).map(|_| self.object()).collect::<Result<_, _>>()?)),
+            _ => Err(Self::corrupt()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Dust, Entry};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, SystemTime};
+
+    fn path(s: &str) -> Path {
+        s.parse().unwrap()
+    }
+
+    fn operations() -> Vec<(u64, Operation)> {
+        let operations = vec![
+            Operation::MakeNode(path("/a/b")),
+            Operation::Set(path("/a"), "t".to_string(), Object::Boolean(true)),
+            Operation::Set(path("/a/b"), "λ".to_string(), Object::Vector(vec![
+                Object::Bytevector(vec![0, 255]),
+                Object::Char('λ'),
+                Object::Eof,
+                Object::Null,
+                Object::Number(-1.5),
+                Object::Pair(Box::new(Object::Symbol("x".to_string())), Box::new(Object::String("y".to_string()))),
+            ])),
+            Operation::Batch(vec![
+                Operation::MakeNode(path("/c")),
+                Operation::Remove(path("/a"), "t".to_string()),
+                Operation::Batch(vec![]),
+                Operation::SetAcl(path("/c"), Some(Acl::new(Permissions::READ).grant("λ", Permissions::ALL))),
+                Operation::SetAcl(path("/c"), None),
+                Operation::Trimmed(path("/c"), "t".to_string()),
+                Operation::DeleteNode(path("/a/b")),
+            ]),
+        ];
+
+        (1..).zip(operations).collect()
+    }
+
+    fn append(storage: &mut FileStorage, (revision, operation): &(u64, Operation)) {
+        storage.append(*revision, operation).unwrap();
+    }
+
+    #[test]
+    fn test_load_what_was_appended() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let mut storage = FileStorage::open(&log).unwrap();
+        assert_eq!(storage.load().unwrap(), vec![]);
+        operations().iter().for_each(|operation| append(&mut storage, operation));
+        drop(storage);
+
+        assert_eq!(FileStorage::open(&log).unwrap().load().unwrap(), operations());
+    }
+
+    #[test]
+    fn test_torn_record_is_cut_off() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let mut storage = FileStorage::open(&log).unwrap();
+        append(&mut storage, &operations()[0]);
+        let intact = fs::metadata(&log).unwrap().len() as usize;
+        append(&mut storage, &operations()[2]);
+        drop(storage);
+
+        let bytes = fs::read(&log).unwrap();
+
+        for cut in intact..bytes.len() {
+            fs::write(&log, &bytes[..cut]).unwrap();
+
+            let mut storage = FileStorage::open(&log).unwrap();
+            assert_eq!(storage.load().unwrap(), operations()[..1], "cut at {}", cut);
+            assert_eq!(fs::metadata(&log).unwrap().len(), intact as u64);
+
+            // appends continue after the last intact record
+            append(&mut storage, &operations()[1]);
+            drop(storage);
+            assert_eq!(FileStorage::open(&log).unwrap().load().unwrap(), operations()[..2]);
+        }
+
+        let mut corrupt = bytes.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        fs::write(&log, &corrupt).unwrap();
+        assert_eq!(FileStorage::open(&log).unwrap().load().unwrap(), operations()[..1]);
+    }
+
+    #[test]
+    fn test_corrupt_record_is_not_cut_off() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let mut storage = FileStorage::open(&log).unwrap();
+        operations().iter().for_each(|operation| append(&mut storage, operation));
+        drop(storage);
+
+        let bytes = fs::read(&log).unwrap();
+
+        // a flipped bit in the first record, with the rest of the log after it,
+        // whether in its length, its checksums or its payload
+        for at in MAGIC.len()..MAGIC.len() + HEADER + 1 {
+            let mut corrupt = bytes.clone();
+            corrupt[at] ^= 1;
+            fs::write(&log, &corrupt).unwrap();
+
+            let result = FileStorage::open(&log).unwrap().load();
+            assert!(matches!(result, Err(DustError::StorageError(_))), "flipped at {}", at);
+            assert_eq!(fs::read(&log).unwrap(), corrupt);
+        }
+    }
+
+    #[test]
+    fn test_append_waits_for_the_compacted_log() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+        let moved = directory.path().join("moved.log");
+
+        let mut storage = FileStorage::open(&log).unwrap();
+        append(&mut storage, &operations()[0]);
+        storage.compact(&operations()[..1]).unwrap();
+
+        // as if opening the log had failed right after the compaction renamed it
+        storage.replaced = true;
+        fs::rename(&log, &moved).unwrap();
+        let (revision, operation) = &operations()[1];
+        assert!(storage.append(*revision, operation).is_err());
+        assert_eq!(fs::read(&moved).unwrap().len() as u64, storage.len);
+
+        fs::rename(&moved, &log).unwrap();
+        append(&mut storage, &operations()[1]);
+        drop(storage);
+        assert_eq!(FileStorage::open(&log).unwrap().load().unwrap(), operations()[..2]);
+    }
+
+    #[test]
+    fn test_log_is_open_once() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let storage = FileStorage::open(&log).unwrap();
+        assert!(matches!(FileStorage::open(&log), Err(DustError::StorageError(_))));
+
+        drop(storage);
+        FileStorage::open(&log).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_replaces_the_log() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let mut storage = FileStorage::open(&log).unwrap().with_compaction_threshold(3);
+        append(&mut storage, &operations()[0]);
+        append(&mut storage, &operations()[1]);
+        assert!(!storage.wants_compaction());
+        append(&mut storage, &operations()[3]);
+        assert!(storage.wants_compaction());
+
+        storage.compact(&operations()[..1]).unwrap();
+        assert!(!storage.wants_compaction());
+        append(&mut storage, &operations()[1]);
+        drop(storage);
+
+        assert_eq!(FileStorage::open(&log).unwrap().load().unwrap(), operations()[..2]);
+        assert!(!compaction_path(&log).exists());
+    }
+
+    #[test]
+    fn test_refuses_other_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        fs::write(&log, "not a log at all").unwrap();
+        assert!(FileStorage::open(&log).is_err());
+
+        // a header cut short by a crash is just a new log
+        fs::write(&log, &MAGIC[..3]).unwrap();
+        assert_eq!(FileStorage::open(&log).unwrap().load().unwrap(), vec![]);
+    }
+
+    const WRITER: &str = "DUST_CRASH_WRITER";
+
+    /// Run in a child process by `test_kill_writer_mid_operation`: keeps adding
+    /// nodes `/n<i>` holding `i`, then counting them in `/count`, until it is killed.
+    fn writer(log: &str) -> ! {
+        let mut dust = Dust::open(FileStorage::open(log).unwrap().with_compaction_threshold(16)).unwrap();
+
+        let mut next = match dust.get("count").unwrap() {
+            Some(Object::Number(count)) => count as usize + 1,
+            _ => 0,
+        };
+
+        loop {
+            dust.make_node(path(&format!("/n{}", next))).unwrap();
+            dust.change_node(path(&format!("/n{}", next))).unwrap();
+            dust.set("i", Object::Number(next as f64)).unwrap();
+            dust.change_node(Path::root()).unwrap();
+            dust.set("count", Object::Number(next as f64)).unwrap();
+            next += 1;
+        }
+    }
+
+    /// The nodes written before `count` are complete, and at most one more was
+    /// started after it.
+    fn check(dust: &mut Dust) -> usize {
+        let counted = match dust.get("count").unwrap() {
+            Some(Object::Number(count)) => count as usize + 1,
+            None => 0,
+            other => panic!("unexpected count {:?}", other),
+        };
+
+        let mut nodes: Vec<usize> = dust
+            .list(Path::root())
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                Entry::Node(name) => Some(name[1..].parse().unwrap()),
+                Entry::Key(_, _) => None,
+            })
+            .collect();
+        nodes.sort();
+
+        assert!(nodes.len() == counted || nodes.len() == counted + 1, "{} nodes, count {}", nodes.len(), counted);
+        assert_eq!(nodes, (0..nodes.len()).collect::<Vec<_>>());
+
+        for i in 0..counted {
+            dust.change_node(path(&format!("/n{}", i))).unwrap();
+            assert_eq!(dust.get("i").unwrap(), Some(Object::Number(i as f64)));
+        }
+
+        dust.change_node(Path::root()).unwrap();
+        counted
+    }
+
+    #[test]
+    fn test_kill_writer_mid_operation() {
+        if let Ok(log) = std::env::var(WRITER) {
+            writer(&log);
+        }
+
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+        let mut counted = 0;
+
+        for round in 0..6 {
+            let mut child = Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "storage::tests::test_kill_writer_mid_operation"])
+                .env(WRITER, &log)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+
+            let jitter = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().subsec_nanos() % 50;
+            std::thread::sleep(Duration::from_millis(100 + 30 * round + jitter as u64));
+
+            assert!(child.try_wait().unwrap().is_none(), "the writer stopped on its own");
+            child.kill().unwrap();
+            child.wait().unwrap();
+
+            let mut dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+            let now = check(&mut dust);
+            assert!(now >= counted, "lost nodes: {} after {}", now, counted);
+            counted = now;
+        }
+
+        assert!(counted > 0, "the writer never got going");
+    }
+}
@@ -1,74 +1,382 @@
-use std::cell::RefCell;
-use std::collections::HashMap;
-use std::rc::{Rc, Weak};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
-use crate::object::Object;
+use crate::acl::{self, Acl, Permissions, Principal};
+use crate::object::{Object, ObjectKind};
 use crate::path::Path;
+use crate::storage::Operation;
+use crate::DustError;
 
+/// What a node contains under a name: a child node or a bound key.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Entry {
+    Node(String),
+    Key(String, ObjectKind),
+}
+
+impl Entry {
+    pub fn name(&self) -> &str {
+        match self {
+            Entry::Node(name) => name,
+            Entry::Key(name, _) => name,
+        }
+    }
+}
+
+/// A value a key had, from the revision of the tree that set it. A value of
+/// None means the key was removed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Version {
+    pub revision: u64,
+    pub value: Option<Object>,
+}
+
+/// A key's recent versions, oldest first; the last version is the current one.
+/// `trimmed` is set once older versions have been dropped, so that the history no
+/// longer goes back to the key's first version.
+#[derive(Clone, Default)]
+struct Binding {
+    history: VecDeque<Version>,
+    trimmed: bool,
+}
+
+impl Binding {
+    /// The current value; None if the key is not bound.
+    fn value(&self) -> Option<&Object> {
+        self.history.back().and_then(|version| version.value.as_ref())
+    }
+
+    fn push(&mut self, version: Version, limit: usize) {
+        self.history.push_back(version);
+        self.trim(limit);
+    }
+
+    fn trim(&mut self, limit: usize) {
+        while self.history.len() > limit.max(1) {
+            self.history.pop_front();
+            self.trimmed = true;
+        }
+    }
+}
+
+/// Locks `lock` for reading. A panic elsewhere while it was held cannot leave the
+/// tree half-changed, so a poisoned lock is used as is.
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Locks `lock` for writing; see `read`.
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Numbers the nodes, so that a node can be told apart from another made
+/// later at the same path.
+static NODES: AtomicU64 = AtomicU64::new(1);
+
+/// A node of the Dust tree, owning its children.
+///
+/// Nodes have no locks of their own: a Dust tree keeps the whole of it behind
+/// one lock.
+#[derive(Clone)]
 pub struct SymbolTable {
-    parent: Option<Rc::<RefCell<SymbolTable>>>,
-    table: HashMap<String, Rc<RefCell<Object>>>,
-    children: HashMap<String, Weak<RefCell<SymbolTable>>>,
+    id: u64,
+    table: HashMap<String, Binding>,
+    children: HashMap<String, SymbolTable>,
     path: Path,
+    acl: Option<Acl>,
 }
 
 impl SymbolTable {
-    pub fn new(parent: Option<Rc<RefCell<SymbolTable>>>, path: Path  ) -> Self {
+    pub(crate) fn new(path: Path) -> Self {
         SymbolTable {
-            parent,
+            id: NODES.fetch_add(1, Ordering::Relaxed),
             table: HashMap::new(),
             children: HashMap::new(),
             path,
+            acl: None,
         }
     }
 
-    pub fn get(&self, key: &str) -> Option<Rc<RefCell<Object>>> {
-        match self.table.get(key) {
-            Some(value) => Some(value.clone()),
-            None => match &self.parent {
-                Some(parent) => parent.borrow().get(key),
-                None => None,
-            },
-        }
+    /// Identifies this node; copies of it share the id.
+    pub(crate) fn id(&self) -> u64 {
+        self.id
     }
 
-    pub fn set(&mut self, key: &str, value: Rc<RefCell<Object>>) {
-        self.table.insert(key.to_string(), value);
+    /// The node at `path`, taken as relative to this one.
+    pub fn node(&self, path: &Path) -> Option<&SymbolTable> {
+        path.as_vector().iter().try_fold(self, |node, key| node.children.get(key))
     }
 
-    pub fn make_node(self_ref: Rc<RefCell<Self>>, path: Path) -> Option<Weak<RefCell<SymbolTable>>> {
-        let mut current = self_ref.clone();
-        
+    /// Like `node`, for changing it.
+    pub(crate) fn node_mut(&mut self, path: &Path) -> Option<&mut SymbolTable> {
+        path.as_vector().iter().try_fold(self, |node, key| node.children.get_mut(key))
+    }
+
+    /// The node at `path`, taken as relative to this one, made along with any
+    /// missing ancestors if need be. The paths of the nodes made are appended
+    /// to `made`, parents first.
+    pub(crate) fn make_node(&mut self, path: &Path, made: &mut Vec<Path>) -> &mut SymbolTable {
+        let mut node = self;
         for key in path.as_vector() {
-            current = current.clone().borrow().get_child(key.as_str())
-            .and_then(|child| Some(child.upgrade().unwrap()))
-            .unwrap_or(
-                SymbolTable::new_child(current.clone(), key.as_str()).unwrap().upgrade().unwrap()
-            );
+            let child = node.path.clone() + key.as_str();
+            node = node.children.entry(key.clone()).or_insert_with(|| {
+                made.push(child.clone());
+                SymbolTable::new(child)
+            });
+        }
+        node
+    }
+
+    /// Looks `key` up in the node at `path`, then in its ancestors up to this
+    /// one, giving the path of the node it was found in.
+    pub fn find(&self, path: &Path, key: &str) -> Option<(Path, Object)> {
+        let mut node = self;
+        let mut found = node.get_own(key).map(|value| (node.path.clone(), value));
+
+        for name in path.as_vector() {
+            node = node.children.get(&name)?;
+            if let Some(value) = node.get_own(key) {
+                found = Some((node.path.clone(), value));
+            }
         }
 
-        return Some(Rc::<RefCell<SymbolTable>>::downgrade(&current));
+        found
+    }
+
+    /// Looks `key` up in this node only.
+    pub fn get_own(&self, key: &str) -> Option<Object> {
+        self.table.get(key).and_then(|binding| binding.value().cloned())
     }
 
-    pub fn new_child(self_ref: Rc<RefCell<Self>>, key: &str) -> Option<Weak<RefCell<SymbolTable>>>{
-        let path = self_ref.borrow().path.clone() + key;
+    /// Binds `key` to `value` as of `revision`, keeping at most `limit` versions of it.
+    pub(crate) fn set(&mut self, key: &str, value: Object, revision: u64, limit: usize) {
+        let binding = self.table.entry(key.to_string()).or_default();
+        binding.push(Version { revision, value: Some(value) }, limit);
+    }
 
-        let parent = Some(self_ref.clone());
-    
-        let child = SymbolTable::new(
-            parent,
-            path,
-        );
+    /// Unbinds `key` as of `revision`, returning the value it was bound to. A key
+    /// with no versions at all still gets the removal as its first one, as when a
+    /// snapshot whose oldest kept version of the key is a removal is replayed.
+    pub(crate) fn remove(&mut self, key: &str, revision: u64, limit: usize) -> Option<Object> {
+        let binding = self.table.entry(key.to_string()).or_default();
+        let value = binding.value().cloned();
+
+        if value.is_some() || binding.history.is_empty() {
+            binding.push(Version { revision, value: None }, limit);
+        }
+        value
+    }
 
-        self_ref.borrow_mut().children.insert(key.to_string(), Rc::downgrade(&Rc::new(RefCell::new(child))))
+    /// The current version of `key` in this node; revision 0 if it is not bound.
+    pub fn version(&self, key: &str) -> Version {
+        match self.table.get(key).and_then(|binding| binding.history.back()) {
+            Some(version) if version.value.is_some() => version.clone(),
+            _ => Version { revision: 0, value: None },
+        }
+    }
+
+    /// The versions of `key` in this node that are still kept, oldest first.
+    pub fn history(&self, key: &str) -> Vec<Version> {
+        self.table
+            .get(key)
+            .map(|binding| binding.history.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Drops all but the last `limit` versions of every key.
+    pub(crate) fn trim_history(&mut self, limit: usize) {
+        self.table.values_mut().for_each(|binding| binding.trim(limit));
+    }
+
+    /// Notes that versions of `key` older than those kept have been dropped.
+    pub(crate) fn set_trimmed(&mut self, key: &str) {
+        if let Some(binding) = self.table.get_mut(key) {
+            binding.trimmed = true;
+        }
     }
 
-    pub fn get_child(&self, key: &str) -> Option<Weak<RefCell<SymbolTable>>> {
-        match self.children.get(key) {
-            Some(value) => Some(value.clone()),
-            None => None,
+    /// The value `key` had in this node at `revision`; None before its first
+    /// version. Fails if versions that far back have been dropped.
+    pub fn get_at(&self, key: &str, revision: u64) -> Result<Option<Object>, DustError> {
+        let binding = match self.table.get(key) {
+            Some(binding) => binding,
+            None => return Ok(None),
+        };
+
+        match binding.history.iter().rev().find(|version| version.revision <= revision) {
+            Some(version) => Ok(version.value.clone()),
+            None if !binding.trimmed => Ok(None),
+            None => Err(DustError::RevisionError(format!(
+                "History of {} in {} does not go back to revision {}",
+                key, self.path, revision
+            ))),
         }
     }
 
+    pub fn get_child(&self, key: &str) -> Option<&SymbolTable> {
+        self.children.get(key)
+    }
+
+    pub(crate) fn remove_child(&mut self, key: &str) -> Option<SymbolTable> {
+        self.children.remove(key)
+    }
+
+    /// Attaches a node that was removed from under this one back under its old
+    /// name. Fails if the name has been taken in the meantime.
+    pub(crate) fn insert_child(&mut self, key: &str, child: SymbolTable) -> Result<(), DustError> {
+        if self.children.contains_key(key) {
+            return Err(DustError::SymbolTableError(format!("Node already exists: {}", child.path)));
+        }
+        self.children.insert(key.to_string(), child);
+        Ok(())
+    }
+
+    /// The child nodes followed by the keys bound in this node (not its ancestors),
+    /// each sorted by name.
+    pub fn list(&self) -> Vec<Entry> {
+        let mut nodes: Vec<Entry> = self.children.keys().map(|name| Entry::Node(name.clone())).collect();
+        let mut keys: Vec<Entry> = self
+            .table
+            .iter()
+            .filter_map(|(name, binding)| Some(Entry::Key(name.clone(), binding.value()?.kind())))
+            .collect();
+
+        nodes.sort_by(|a, b| a.name().cmp(b.name()));
+        keys.sort_by(|a, b| a.name().cmp(b.name()));
+
+        nodes.extend(keys);
+        nodes
+    }
+
+    /// True if the node has neither bindings nor children.
+    pub fn is_empty(&self) -> bool {
+        self.table.values().all(|binding| binding.value().is_none()) && self.children.is_empty()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The node's own ACL; None if it has the one of its parent.
+    pub fn acl(&self) -> Option<&Acl> {
+        self.acl.as_ref()
+    }
+
+    pub(crate) fn set_acl(&mut self, acl: Option<Acl>) {
+        self.acl = acl;
+    }
+
+    /// Appends operations, each with the revision it is applied at, that rebuild this
+    /// node and the versions kept of its keys, but not its children. The node itself
+    /// is made at `revision`.
+    pub fn snapshot(&self, revision: u64, operations: &mut Vec<(u64, Operation)>) {
+        operations.push((revision, Operation::MakeNode(self.path.clone())));
+        if let Some(acl) = &self.acl {
+            operations.push((revision, Operation::SetAcl(self.path.clone(), Some(acl.clone()))));
+        }
+
+        let mut keys: Vec<&String> = self.table.keys().collect();
+        keys.sort();
+        for key in keys {
+            let binding = &self.table[key];
+            for version in &binding.history {
+                let operation = match &version.value {
+                    Some(value) => Operation::Set(self.path.clone(), key.clone(), value.clone()),
+                    None => Operation::Remove(self.path.clone(), key.clone()),
+                };
+                operations.push((version.revision, operation));
+            }
+            if binding.trimmed {
+                operations.push((revision, Operation::Trimmed(self.path.clone(), key.clone())));
+            }
+        }
+    }
+
+    /// Appends operations that rebuild this node with the current values of its
+    /// keys, but not its children.
+    pub fn contents(&self, operations: &mut Vec<Operation>) {
+        operations.push(Operation::MakeNode(self.path.clone()));
+        if let Some(acl) = &self.acl {
+            operations.push(Operation::SetAcl(self.path.clone(), Some(acl.clone())));
+        }
+
+        let mut keys: Vec<(&String, &Object)> = self
+            .table
+            .iter()
+            .filter_map(|(key, binding)| Some((key, binding.value()?)))
+            .collect();
+        keys.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, value) in keys {
+            operations.push(Operation::Set(self.path.clone(), key.clone(), value.clone()));
+        }
+    }
+
+    /// Calls `f` with each node of the subtree under this one, parents before
+    /// children and siblings by name.
+    pub fn visit(&self, f: &mut impl FnMut(&SymbolTable)) {
+        f(self);
+
+        let mut children: Vec<(&String, &SymbolTable)> = self.children.iter().collect();
+        children.sort_by(|a, b| a.0.cmp(b.0));
+        for (_, child) in children {
+            child.visit(f);
+        }
+    }
+
+    /// Like `visit`, for changing each node, in no particular order.
+    pub(crate) fn visit_mut(&mut self, f: &mut impl FnMut(&mut SymbolTable)) {
+        f(self);
+        for child in self.children.values_mut() {
+            child.visit_mut(f);
+        }
+    }
+}
+
+/// Depth-first, pre-order iterator over the entries of a subtree. Each entry is
+/// yielded with the path of the node containing it; a child node's own entries
+/// follow it immediately. The entries are those of the tree as it was when the
+/// walk began.
+pub struct Walk {
+    entries: std::vec::IntoIter<(Path, Entry)>,
+}
+
+impl Walk {
+    /// Walks only the nodes `principal` may read, starting from `node`, whose
+    /// ACL is `acl`. Nodes it may not read are yielded, but not their entries.
+    pub(crate) fn readable_by(node: &SymbolTable, acl: Option<&Acl>, principal: &Principal) -> Self {
+        let mut entries = vec![];
+        Walk::collect(node, acl, principal, &mut entries);
+
+        Walk {
+            entries: entries.into_iter(),
+        }
+    }
+
+    fn collect(node: &SymbolTable, acl: Option<&Acl>, principal: &Principal, entries: &mut Vec<(Path, Entry)>) {
+        if !acl::permits(acl, principal, Permissions::READ) {
+            return;
+        }
+
+        for entry in node.list() {
+            let child = match &entry {
+                Entry::Node(name) => node.children.get(name),
+                Entry::Key(..) => None,
+            };
+
+            entries.push((node.path.clone(), entry));
+            if let Some(child) = child {
+                Walk::collect(child, child.acl().or(acl), principal, entries);
+            }
+        }
+    }
 }
 
+impl Iterator for Walk {
+    type Item = (Path, Entry);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next()
+    }
+}
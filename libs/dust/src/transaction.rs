@@ -0,0 +1,483 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::acl::{Acl, Permissions, Principal};
+use crate::object::Object;
+use crate::path::Path;
+use crate::storage::Operation;
+use crate::symboltable::Entry;
+use crate::{Dust, DustError, Tree, deletable, require};
+
+/// A set of changes to a Dust tree made by `Dust::transaction`.
+///
+/// Reads see the tree as it was when the transaction began, plus the changes made
+/// through the transaction itself. Changes are buffered, and only reach the tree
+/// and its storage if the transaction commits. Relative paths are resolved against
+/// the node that was current when the transaction began.
+///
+/// Transactions on a tree run one at a time, and no other changes are made to the
+/// tree while one runs, so one that commits never overwrites changes it did not see.
+/// Each change is checked against the ACLs as the transaction has left them, for
+/// the principal of the cursor that began it.
+pub struct Transaction {
+    tree: Arc<Tree>,
+    current: Path,
+    principal: Principal,
+    /// Nodes of the tree the transaction has deleted, with everything under them.
+    deleted: Vec<Path>,
+    /// The keys the transaction has bound, or with None unbound, by node. Every node
+    /// the transaction has made is here, if only with no keys.
+    nodes: HashMap<Path, HashMap<String, Option<Object>>>,
+    operations: Vec<Operation>,
+}
+
+impl Transaction {
+    fn new(dust: &Dust) -> Self {
+        Transaction {
+            tree: dust.tree.clone(),
+            current: dust.current.clone(),
+            principal: dust.principal.clone(),
+            deleted: vec![],
+            nodes: HashMap::new(),
+            operations: vec![],
+        }
+    }
+
+    fn absolute(&self, path: &Path) -> Path {
+        self.current.join(path)
+    }
+
+    /// Whether the transaction has deleted the node of the tree at the absolute `path`.
+    fn deleted(&self, path: &Path) -> bool {
+        self.deleted.iter().any(|deleted| path.includes(deleted))
+    }
+
+    /// Whether there is a node at the absolute `path`, as the transaction has left the tree.
+    fn exists(&self, path: &Path) -> bool {
+        self.nodes.contains_key(path) || (!self.deleted(path) && self.tree.nodes().node(path).is_some())
+    }
+
+    /// The absolute path of the node at `path`, which must exist.
+    fn resolve(&self, path: &Path) -> Result<Path, DustError> {
+        let path = self.absolute(path);
+
+        match self.exists(&path) {
+            true => Ok(path),
+            false => Err(DustError::SymbolTableError(format!("Path not found: {}", path))),
+        }
+    }
+
+    /// The value of `key` in the node at the absolute `path` only.
+    fn get_own(&self, path: &Path, key: &str) -> Option<Object> {
+        if let Some(value) = self.nodes.get(path).and_then(|keys| keys.get(key)) {
+            return value.clone();
+        }
+
+        if self.deleted(path) {
+            return None;
+        }
+
+        self.tree.nodes().node(path)?.get_own(key)
+    }
+
+    /// The ACL of the node at the absolute `path`, as `inherited_acl` gives it. Nodes the
+    /// transaction has made have none of their own.
+    fn acl(&self, path: &Path) -> Option<Acl> {
+        let own = |path: &Path| match self.deleted(path) {
+            true => None,
+            false => self.tree.nodes().node(path).and_then(|node| node.acl().cloned()),
+        };
+
+        let mut current = Path::root();
+        let mut acl = own(&current);
+
+        for key in path.as_vector() {
+            current = current + key.as_str();
+            if !self.exists(&current) {
+                break;
+            }
+
+            if let Some(own) = own(&current) {
+                acl = Some(own);
+            }
+        }
+
+        acl
+    }
+
+    fn check(&self, path: &Path, permissions: Permissions) -> Result<(), DustError> {
+        require(self.acl(path).as_ref(), &self.principal, path, permissions)
+    }
+
+    /// The entries of the node at the absolute `path`, ordered as `SymbolTable::list` orders them.
+    fn entries(&self, path: &Path) -> Vec<Entry> {
+        let mut nodes = BTreeSet::new();
+        let mut keys = BTreeMap::new();
+
+        let head = self.tree.nodes();
+        if !self.deleted(path)
+            && let Some(node) = head.node(path)
+        {
+            for entry in node.list() {
+                match entry {
+                    Entry::Node(name) if self.deleted(&(path.clone() + name.as_str())) => {}
+                    Entry::Node(name) => {
+                        nodes.insert(name);
+                    }
+                    Entry::Key(name, kind) => {
+                        keys.insert(name, kind);
+                    }
+                }
+            }
+        }
+
+        let made = self.nodes.keys().filter(|node| node.parent().as_ref() == Some(path));
+        nodes.extend(made.filter_map(|node| node.file_name().map(str::to_string)));
+
+        for (name, value) in self.nodes.get(path).into_iter().flatten() {
+            match value {
+                Some(value) => keys.insert(name.clone(), value.kind()),
+                None => keys.remove(name),
+            };
+        }
+
+        nodes
+            .into_iter()
+            .map(Entry::Node)
+            .chain(keys.into_iter().map(|(name, kind)| Entry::Key(name, kind)))
+            .collect()
+    }
+
+    /// Adds the absolute paths of the node at `path` and the nodes under it to `paths`.
+    fn subtree(&self, path: &Path, paths: &mut Vec<Path>) {
+        paths.push(path.clone());
+
+        for entry in self.entries(path) {
+            if let Entry::Node(name) = entry {
+                self.subtree(&(path.clone() + name.as_str()), paths);
+            }
+        }
+    }
+
+    /// Looks `key` up in the node at `path`, then in its ancestors, as `Dust::get` does.
+    pub fn get(&self, path: Path, key: &str) -> Result<Option<Object>, DustError> {
+        let path = self.resolve(&path)?;
+        self.check(&path, Permissions::READ)?;
+
+        let mut node = Some(path);
+        while let Some(path) = node {
+            if let Some(value) = self.get_own(&path, key) {
+                self.check(&path, Permissions::READ)?;
+                return Ok(Some(value));
+            }
+            node = path.parent();
+        }
+
+        Ok(None)
+    }
+
+    /// Binds `key` in the existing node at `path`.
+    pub fn set(&mut self, path: Path, key: &str, value: Object) -> Result<(), DustError> {
+        let path = self.resolve(&path)?;
+        self.check(&path, Permissions::WRITE)?;
+
+        self.nodes.entry(path.clone()).or_default().insert(key.to_string(), Some(value.clone()));
+        self.operations.push(Operation::Set(path, key.to_string(), value));
+        Ok(())
+    }
+
+    /// Unbinds `key` in the node at `path`, returning the value it was bound to.
+    pub fn remove(&mut self, path: Path, key: &str) -> Result<Option<Object>, DustError> {
+        let path = self.resolve(&path)?;
+        self.check(&path, Permissions::WRITE)?;
+
+        let value = self.get_own(&path, key);
+        if value.is_some() {
+            self.nodes.entry(path.clone()).or_default().insert(key.to_string(), None);
+            self.operations.push(Operation::Remove(path, key.to_string()));
+        }
+        Ok(value)
+    }
+
+    /// Makes the node at `path` and any missing ancestors, on the same terms as `Dust::make_node`.
+    pub fn make_node(&mut self, path: Path) -> Result<(), DustError> {
+        let path = self.absolute(&path);
+        self.check(&path.parent().unwrap_or_else(Path::root), Permissions::CREATE_CHILD)?;
+
+        if self.exists(&path) {
+            return Ok(());
+        }
+
+        let mut current = Path::root();
+        for key in path.as_vector() {
+            current = current + key.as_str();
+            if !self.exists(&current) {
+                self.nodes.insert(current.clone(), HashMap::new());
+            }
+        }
+
+        self.operations.push(Operation::MakeNode(path));
+        Ok(())
+    }
+
+    /// Removes the node at `path`, on the same terms as `Dust::delete_node`.
+    pub fn delete_node(&mut self, path: Path, recursive: bool) -> Result<(), DustError> {
+        let absolute = self.absolute(&path);
+        deletable(&self.current, &absolute, &path)?;
+
+        let absolute = self.resolve(&absolute)?;
+        if !recursive && !self.entries(&absolute).is_empty() {
+            return Err(DustError::SymbolTableError(format!("Node not empty: {}", path)));
+        }
+
+        if self.principal != Principal::Root {
+            let mut paths = vec![];
+            self.subtree(&absolute, &mut paths);
+            paths.iter().try_for_each(|path| self.check(path, Permissions::DELETE))?;
+        }
+
+        // what the transaction made there goes too
+        self.nodes.retain(|node, _| !node.includes(&absolute));
+        self.deleted.push(absolute.clone());
+        self.operations.push(Operation::DeleteNode(absolute));
+        Ok(())
+    }
+
+    pub fn list(&self, path: Path) -> Result<Vec<Entry>, DustError> {
+        let path = self.resolve(&path)?;
+        self.check(&path, Permissions::READ)?;
+        Ok(self.entries(&path))
+    }
+}
+
+impl Dust {
+    /// Runs `f` in a transaction. If `f` succeeds, its changes are recorded in the
+    /// storage as a single batch and then made to the tree; if it fails, or the
+    /// changes cannot be recorded, the tree is left as it was.
+    ///
+    /// Changes to the tree wait for the transaction to finish. A change `f` makes
+    /// through a cursor, nested transactions included, fails rather than wait for it.
+    pub fn transaction<T>(
+        &self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, DustError>,
+    ) -> Result<T, DustError> {
+        let mut storage = self.tree.lock()?;
+
+        let mut transaction = Transaction::new(self);
+        let result = f(&mut transaction)?;
+
+        if !transaction.operations.is_empty() {
+            self.tree.commit(&mut storage, Operation::Batch(transaction.operations))?;
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acl, FileStorage, ObjectKind};
+
+    fn path(s: &str) -> Path {
+        s.parse().unwrap()
+    }
+
+    fn get(dust: &Dust, node: &str, key: &str) -> Option<Object> {
+        dust.transaction(|tx| tx.get(path(node), key)).unwrap()
+    }
+
+    fn build() -> Dust {
+        let mut dust = Dust::new();
+        dust.make_node(path("/a")).unwrap();
+        dust.make_node(path("/b")).unwrap();
+        dust.change_node(path("/a")).unwrap();
+        dust.set("x", Object::Number(1.0)).unwrap();
+        dust.change_node(Path::root()).unwrap();
+        dust
+    }
+
+    #[test]
+    fn test_commit() {
+        let dust = build();
+
+        let seen = dust
+            .transaction(|tx| {
+                tx.set(path("/a"), "x", Object::Number(2.0))?;
+                tx.make_node(path("/b/c"))?;
+                tx.set(path("/b/c"), "y", Object::Null)?;
+                tx.delete_node(path("/a"), true)?;
+                tx.make_node(path("/a"))?;
+
+                // the transaction reads its own changes
+                assert_eq!(tx.get(path("/b/c"), "y")?, Some(Object::Null));
+                assert_eq!(tx.get(path("/a"), "x")?, None);
+                tx.get(path("/b/c"), "y")
+            })
+            .unwrap();
+
+        assert_eq!(seen, Some(Object::Null));
+        assert_eq!(get(&dust, "/b/c", "y"), Some(Object::Null));
+        assert_eq!(get(&dust, "/a", "x"), None);
+    }
+
+    #[test]
+    fn test_rollback() {
+        let mut dust = build();
+
+        let result: Result<(), DustError> = dust.transaction(|tx| {
+            tx.set(path("/a"), "x", Object::Number(2.0))?;
+            tx.make_node(path("/b/c"))?;
+            tx.delete_node(path("/b"), false)?;
+            unreachable!("/b is not empty");
+        });
+
+        assert!(result.is_err());
+        assert_eq!(get(&dust, "/a", "x"), Some(Object::Number(1.0)));
+        assert!(dust.change_node(path("/b/c")).is_err());
+    }
+
+    #[test]
+    fn test_reads_are_isolated_from_the_tree() {
+        let dust = build();
+
+        dust.transaction(|tx| {
+            tx.set(path("/a"), "x", Object::Number(2.0))?;
+
+            // nothing reaches the tree before the transaction commits
+            let x = dust.tree.nodes().node(&path("/a")).unwrap().get_own("x");
+            assert_eq!(x, Some(Object::Number(1.0)));
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(get(&dust, "/a", "x"), Some(Object::Number(2.0)));
+    }
+
+    #[test]
+    fn test_cursor_changes_fail_inside() {
+        let dust = build();
+        let other = Dust::new();
+
+        dust.transaction(|tx| {
+            // through a cursor, the tree can be read but not changed
+            assert_eq!(dust.lookup(&path("/a"), "x")?, Some(Object::Number(1.0)));
+            assert!(dust.set("y", Object::Null).is_err());
+            assert!(dust.make_node(path("/c")).is_err());
+            assert!(dust.transaction(|_| Ok(())).is_err());
+
+            // other trees are not held
+            other.set("y", Object::Null)?;
+            tx.set(path("/a"), "x", Object::Number(2.0))
+        })
+        .unwrap();
+
+        assert_eq!(get(&dust, "/a", "x"), Some(Object::Number(2.0)));
+        dust.set("y", Object::Null).unwrap();
+    }
+
+    #[test]
+    fn test_changes_are_read_over_the_tree() {
+        let dust = build();
+        dust.make_node(path("/a/d")).unwrap();
+        dust.set_acl(path("/a"), Some(Acl::new(Permissions::NONE).grant("alice", Permissions::DELETE))).unwrap();
+        let alice = dust.cursor_as(Principal::user("alice")).unwrap();
+
+        alice
+            .transaction(|tx| {
+                tx.set(path("/b"), "y", Object::Null)?;
+                tx.make_node(path("/b/c"))?;
+                assert_eq!(tx.list(path("/b"))?, vec![
+                    Entry::Node("c".to_string()),
+                    Entry::Key("y".to_string(), ObjectKind::Null),
+                ]);
+                assert!(tx.list(path("/a")).is_err());
+
+                // a node made again after being deleted has nothing of the old one, not even its ACL
+                tx.delete_node(path("/a"), true)?;
+                tx.make_node(path("/a"))?;
+                assert_eq!(tx.list(path("/a"))?, vec![]);
+                assert_eq!(tx.get(path("/a"), "x")?, None);
+                assert!(tx.list(path("/a/d")).is_err());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(dust.list(path("/a")).unwrap(), vec![]);
+        assert_eq!(get(&dust, "/b", "y"), Some(Object::Null));
+    }
+
+    #[test]
+    fn test_relative_paths_and_current_node() {
+        let mut dust = build();
+        dust.change_node(path("/a")).unwrap();
+
+        dust.transaction(|tx| {
+            tx.make_node(path("c"))?;
+            tx.set(path("c"), "z", Object::Boolean(true))?;
+            assert!(tx.delete_node(path("/a"), true).is_err(), "/a is the current node");
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(get(&dust, "/a/c", "z"), Some(Object::Boolean(true)));
+    }
+
+    #[test]
+    fn test_changes_are_checked() {
+        let dust = build();
+        dust.set_acl(path("/a"), Some(Acl::new(Permissions::READ).grant("alice", Permissions::ALL))).unwrap();
+        let bob = dust.cursor_as(Principal::user("bob")).unwrap();
+
+        let result = bob.transaction(|tx| {
+            tx.set(path("/b"), "y", Object::Null)?;
+            tx.set(path("/a"), "x", Object::Number(2.0))
+        });
+        assert!(matches!(result, Err(DustError::PermissionError(_))));
+        assert_eq!(get(&dust, "/b", "y"), None);
+
+        let alice = dust.cursor_as(Principal::user("alice")).unwrap();
+        alice
+            .transaction(|tx| {
+                tx.make_node(path("/a/c"))?;
+                tx.set(path("/a/c"), "z", Object::Null)?;
+                assert!(tx.delete_node(path("/b"), false).is_ok());
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(get(&bob, "/a/c", "z"), Some(Object::Null));
+        assert!(bob.transaction(|tx| tx.remove(path("/a/c"), "z")).is_err());
+    }
+
+    #[test]
+    fn test_commit_is_one_record() {
+        let directory = tempfile::tempdir().unwrap();
+        let log = directory.path().join("dust.log");
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        dust.make_node(path("/a")).unwrap();
+        let before = std::fs::metadata(&log).unwrap().len();
+
+        dust.transaction(|tx| {
+            tx.make_node(path("/b"))?;
+            tx.set(path("/a"), "x", Object::Number(1.0))?;
+            tx.set(path("/b"), "y", Object::Number(2.0))
+        })
+        .unwrap();
+        drop(dust);
+
+        let dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        assert_eq!(get(&dust, "/a", "x"), Some(Object::Number(1.0)));
+        assert_eq!(get(&dust, "/b", "y"), Some(Object::Number(2.0)));
+        drop(dust);
+
+        // a commit torn by a crash is lost as a whole
+        let bytes = std::fs::read(&log).unwrap();
+        std::fs::write(&log, &bytes[..bytes.len() - 1]).unwrap();
+        assert!(bytes.len() as u64 > before);
+
+        let mut dust = Dust::open(FileStorage::open(&log).unwrap()).unwrap();
+        assert_eq!(get(&dust, "/a", "x"), None);
+        assert!(dust.change_node(path("/b")).is_err());
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll, Waker};
+
+use futures_core::Stream;
+
+use crate::acl::Principal;
+use crate::object::Object;
+use crate::path::Path;
+
+/// A change made to a Dust tree. Paths are absolute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    KeySet { node: Path, key: String, value: Object },
+    KeyRemoved { node: Path, key: String },
+    NodeCreated(Path),
+    /// The node and everything below it were removed.
+    NodeDeleted(Path),
+}
+
+impl Event {
+    /// The node the change was made to, or that was created or deleted.
+    pub fn path(&self) -> &Path {
+        match self {
+            Event::KeySet { node, .. } => node,
+            Event::KeyRemoved { node, .. } => node,
+            Event::NodeCreated(path) => path,
+            Event::NodeDeleted(path) => path,
+        }
+    }
+
+    /// Whether a watcher of `watched` hears of the event: it happened at or below
+    /// `watched`, or deleted `watched` along with an ancestor.
+    fn concerns(&self, watched: &Path) -> bool {
+        match self {
+            Event::NodeDeleted(path) => path.includes(watched) || watched.includes(path),
+            event => event.path().includes(watched),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+type Callback = dyn Fn(&Event) + Send + Sync;
+
+/// Keeps a callback passed to `Dust::watch` registered. Dropping it unsubscribes.
+pub struct Subscription {
+    _callback: Arc<Callback>,
+}
+
+/// How many events an EventStream holds before it is closed for falling behind.
+const STREAM_CAPACITY: usize = 1024;
+
+#[derive(Default)]
+struct Queue {
+    events: VecDeque<Event>,
+    waker: Option<Waker>,
+    closed: bool,
+    overflowed: bool,
+}
+
+/// The events a `Dust::watch_stream` watcher hears of, in the order the changes
+/// were made. The stream ends once the tree is dropped; dropping the stream
+/// unsubscribes.
+///
+/// At most 1024 events are held for the stream. One that falls further behind
+/// is closed: it yields the events held, then ends, and `overflowed` tells it
+/// that later events were missed.
+pub struct EventStream {
+    queue: Arc<Mutex<Queue>>,
+}
+
+impl EventStream {
+    /// The next event if one is waiting, without waiting for one.
+    pub fn try_next(&mut self) -> Option<Event> {
+        lock(&self.queue).events.pop_front()
+    }
+
+    /// Whether the stream was closed for falling behind.
+    pub fn overflowed(&self) -> bool {
+        lock(&self.queue).overflowed
+    }
+}
+
+impl Stream for EventStream {
+    type Item = Event;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        let mut queue = lock(&self.queue);
+
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+enum Sink {
+    Callback(Weak<Callback>),
+    Queue(Weak<Mutex<Queue>>),
+}
+
+struct Watcher {
+    path: Path,
+    principal: Principal,
+    sink: Sink,
+}
+
+/// The watchers of a tree. Subscriptions and streams are held weakly, and
+/// forgotten once they have been dropped.
+#[derive(Default)]
+pub(crate) struct Watchers {
+    watchers: Mutex<Vec<Watcher>>,
+}
+
+impl Watchers {
+    pub(crate) fn watch(&self, path: Path, principal: Principal, callback: Arc<Callback>) -> Subscription {
+        lock(&self.watchers).push(Watcher {
+            path,
+            principal,
+            sink: Sink::Callback(Arc::downgrade(&callback)),
+        });
+
+        Subscription {
+            _callback: callback,
+        }
+    }
+
+    pub(crate) fn stream(&self, path: Path, principal: Principal) -> EventStream {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+
+        lock(&self.watchers).push(Watcher {
+            path,
+            principal,
+            sink: Sink::Queue(Arc::downgrade(&queue)),
+        });
+
+        EventStream { queue }
+    }
+
+    /// Delivers `events` to the watchers they concern, and whose principal
+    /// `may_hear` them. Callbacks are called without the list of watchers locked,
+    /// so they may watch or unsubscribe.
+    pub(crate) fn notify(&self, events: &[Event], may_hear: impl Fn(&Principal, &Event) -> bool) {
+        if events.is_empty() {
+            return;
+        }
+
+        let mut callbacks = vec![];
+        let mut queues = vec![];
+
+        lock(&self.watchers).retain(|watcher| match &watcher.sink {
+            Sink::Callback(callback) => callback
+                .upgrade()
+                .map(|callback| callbacks.push((watcher.path.clone(), watcher.principal.clone(), callback)))
+                .is_some(),
+            Sink::Queue(queue) => queue
+                .upgrade()
+                .filter(|queue| !lock(queue).closed)
+                .map(|queue| queues.push((watcher.path.clone(), watcher.principal.clone(), queue)))
+                .is_some(),
+        });
+
+        let heard = |path: &Path, principal: &Principal| {
+            events
+                .iter()
+                .filter(|event| event.concerns(path) && may_hear(principal, event))
+                .cloned()
+                .collect::<Vec<Event>>()
+        };
+
+        for (path, principal, queue) in queues {
+            let heard = heard(&path, &principal);
+            let mut queue = lock(&queue);
+
+            // the events that did not fit are lost, so the stream ends before them
+            if queue.events.len() + heard.len() > STREAM_CAPACITY {
+                queue.closed = true;
+                queue.overflowed = true;
+            } else {
+                queue.events.extend(heard);
+            }
+
+            if (queue.closed || !queue.events.is_empty())
+                && let Some(waker) = queue.waker.take()
+            {
+                waker.wake();
+            }
+        }
+
+        for (path, principal, callback) in callbacks {
+            heard(&path, &principal).iter().for_each(|event| callback(event));
+        }
+    }
+}
+
+impl Drop for Watchers {
+    fn drop(&mut self) {
+        for watcher in lock(&self.watchers).drain(..) {
+            if let Sink::Queue(queue) = watcher.sink
+                && let Some(queue) = queue.upgrade()
+            {
+                let mut queue = lock(&queue);
+                queue.closed = true;
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Acl, Dust, DustError, Permissions};
+
+    fn path(s: &str) -> Path {
+        s.parse().unwrap()
+    }
+
+    fn record(dust: &Dust, watched: &str) -> (Subscription, Arc<Mutex<Vec<Event>>>) {
+        let events = Arc::new(Mutex::new(vec![]));
+        let heard = events.clone();
+        let subscription = dust.watch(path(watched), move |event| lock(&heard).push(event.clone())).unwrap();
+        (subscription, events)
+    }
+
+    fn key_set(node: &str, key: &str, value: Object) -> Event {
+        Event::KeySet {
+            node: path(node),
+            key: key.to_string(),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_watch() {
+        let mut dust = Dust::new();
+        let (subscription, events) = record(&dust, "/services");
+
+        dust.make_node(path("/services/api/config")).unwrap();
+        dust.make_node(path("/users")).unwrap();
+        dust.change_node(path("/services/api")).unwrap();
+        dust.set("port", Object::Number(8080.0)).unwrap();
+        assert_eq!(dust.remove("port").unwrap(), Some(Object::Number(8080.0)));
+        assert_eq!(dust.remove("port").unwrap(), None);
+        dust.change_node(Path::root()).unwrap();
+        dust.set("elsewhere", Object::Null).unwrap();
+        dust.delete_node(path("/services/api"), true).unwrap();
+
+        assert_eq!(*lock(&events), vec![
+            Event::NodeCreated(path("/services")),
+            Event::NodeCreated(path("/services/api")),
+            Event::NodeCreated(path("/services/api/config")),
+            key_set("/services/api", "port", Object::Number(8080.0)),
+            Event::KeyRemoved {
+                node: path("/services/api"),
+                key: "port".to_string(),
+            },
+            Event::NodeDeleted(path("/services/api")),
+        ]);
+
+        drop(subscription);
+        dust.make_node(path("/services/db")).unwrap();
+        assert_eq!(lock(&events).len(), 6);
+    }
+
+    #[test]
+    fn test_watch_hears_ancestor_deleted_and_restored() {
+        let dust = Dust::new();
+        dust.make_node(path("/a/b/c")).unwrap();
+        let (_subscription, events) = record(&dust, "/a/b/c");
+
+        let a = dust.delete_node(path("/a"), true).unwrap();
+        dust.restore_node(a).unwrap();
+
+        assert_eq!(*lock(&events), vec![
+            Event::NodeDeleted(path("/a")),
+            Event::NodeCreated(path("/a/b/c")),
+        ]);
+    }
+
+    #[test]
+    fn test_watch_transaction() {
+        let dust = Dust::new();
+        dust.make_node(path("/a")).unwrap();
+        let (_subscription, events) = record(&dust, "/");
+
+        let rolled_back: Result<(), DustError> = dust.transaction(|tx| {
+            tx.set(path("/a"), "x", Object::Null)?;
+            tx.delete_node(path("/nope"), false)
+        });
+        assert!(rolled_back.is_err());
+        assert_eq!(*lock(&events), vec![]);
+
+        dust.transaction(|tx| {
+            tx.set(path("/a"), "x", Object::Null)?;
+            tx.make_node(path("/b"))?;
+            tx.remove(path("/a"), "x")?;
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(*lock(&events), vec![
+            key_set("/a", "x", Object::Null),
+            Event::NodeCreated(path("/b")),
+            Event::KeyRemoved {
+                node: path("/a"),
+                key: "x".to_string(),
+            },
+        ]);
+    }
+
+    #[test]
+    fn test_callback_cannot_change_the_tree() {
+        let dust = Dust::new();
+        let cursor = dust.cursor();
+        let results = Arc::new(Mutex::new(vec![]));
+
+        let heard = results.clone();
+        let _subscription = dust
+            .watch(path("/"), move |_| lock(&heard).push(cursor.make_node(path("/echo")).is_ok()))
+            .unwrap();
+
+        dust.make_node(path("/a")).unwrap();
+        assert_eq!(*lock(&results), vec![false]);
+        assert!(dust.list(path("/echo")).is_err());
+    }
+
+    #[test]
+    fn test_watch_hears_only_what_it_may_read() {
+        let dust = Dust::new();
+        dust.make_node(path("/open")).unwrap();
+        dust.make_node(path("/closed")).unwrap();
+        dust.set_acl(path("/closed"), Some(Acl::new(Permissions::NONE))).unwrap();
+
+        let bob = dust.cursor_as(Principal::user("bob")).unwrap();
+        assert!(matches!(bob.watch_stream(path("/closed/later")), Err(DustError::PermissionError(_))));
+        let (_subscription, events) = record(&bob, "/");
+
+        let mut root = dust.cursor();
+        for node in ["/open", "/closed"] {
+            root.make_node(path(node) + "child").unwrap();
+            root.change_node(path(node)).unwrap();
+            root.set("x", Object::Null).unwrap();
+        }
+        root.change_node(Path::root()).unwrap();
+        dust.delete_node(path("/closed/child"), false).unwrap();
+
+        // a deleted node is heard of as it could be read before, not as its parent can
+        dust.set_acl(path("/open/child"), Some(Acl::new(Permissions::NONE))).unwrap();
+        dust.delete_node(path("/open/child"), false).unwrap();
+
+        assert_eq!(*lock(&events), vec![
+            Event::NodeCreated(path("/open/child")),
+            key_set("/open", "x", Object::Null),
+        ]);
+    }
+
+    async fn next(stream: &mut EventStream) -> Option<Event> {
+        std::future::poll_fn(|cx| Pin::new(&mut *stream).poll_next(cx)).await
+    }
+
+    #[tokio::test]
+    async fn test_watch_stream() {
+        let dust = Dust::new();
+        let mut stream = dust.watch_stream(path("/config")).unwrap();
+
+        let writer = std::thread::spawn(move || {
+            let mut dust = dust;
+            dust.make_node(path("/config")).unwrap();
+            dust.make_node(path("/other")).unwrap();
+            dust.change_node(path("/config")).unwrap();
+            for i in 0..3 {
+                dust.set("version", Object::Number(i as f64)).unwrap();
+            }
+        });
+
+        assert_eq!(next(&mut stream).await, Some(Event::NodeCreated(path("/config"))));
+        for i in 0..3 {
+            assert_eq!(next(&mut stream).await, Some(key_set("/config", "version", Object::Number(i as f64))));
+        }
+
+        // the writer owned the last cursor on the tree
+        writer.join().unwrap();
+        assert_eq!(next(&mut stream).await, None);
+        assert!(!stream.overflowed());
+    }
+
+    #[tokio::test]
+    async fn test_watch_stream_overflow() {
+        let dust = Dust::new();
+        let mut stream = dust.watch_stream(path("/")).unwrap();
+
+        for i in 0..=STREAM_CAPACITY {
+            dust.set("x", Object::Number(i as f64)).unwrap();
+        }
+
+        // the events held are yielded, then the stream ends though the tree lives on
+        for i in 0..STREAM_CAPACITY {
+            assert_eq!(next(&mut stream).await, Some(key_set("/", "x", Object::Number(i as f64))));
+        }
+        assert_eq!(next(&mut stream).await, None);
+        assert!(stream.overflowed());
+
+        dust.set("x", Object::Null).unwrap();
+        assert_eq!(stream.try_next(), None);
+    }
+}
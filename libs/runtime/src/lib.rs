@@ -1,6 +1,7 @@
 use std::ffi::CString;
 use pyo3::types::PyDict;
 use pyo3::prelude::*;
+use dal::{DalError, Parser};
 
 pub enum Language {
     Dal,
@@ -12,6 +13,15 @@ pub trait Runtime {
     fn language(&self) -> Language;
 
     fn eval(&self, program: &str, expr: Option<&str>) -> Result<String, String>;
+
+    /// Evaluates one interactive entry, returning the printed value if it has one.
+    fn eval_interactive(&self, input: &str) -> Result<Option<String>, String>;
+
+    /// Returns `false` if `input` is a valid prefix of a program that needs more lines.
+    fn is_complete(&self, input: &str) -> bool;
+
+    /// Returns the names bound in the global environment.
+    fn globals(&self) -> Vec<String>;
 }
 
 pub struct PythonRuntime {
@@ -29,6 +39,12 @@ impl PythonRuntime {
     }
 }
 
+impl Default for PythonRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Runtime for PythonRuntime {
 
     fn language(&self) -> Language {
@@ -43,18 +59,19 @@ impl Runtime for PythonRuntime {
             
             let globals = self.globals.bind(py);
 
-            let program = CString::new(program).expect("CString::new failed");
-            let run_result = py.run(&program, Some(&globals), None);
+            let program = CString::new(program).map_err(|e| format!("Python execution error: {}", e))?;
+            let run_result = py.run(&program, Some(globals), None);
 
             match run_result {
                 Ok(_) => {
                     let result = match expr {
                         Some(expr) => {
-                            let expr = CString::new(expr).expect("CString::new failed");
-                            let eval_result = py.eval(&expr, Some(&globals), None);
+                            let eval_result = CString::new(expr)
+                                .map_err(|e| format!("Python execution error: {}", e))
+                                .and_then(|expr| py.eval(&expr, Some(globals), None).map_err(|e| format!("Python execution error: {}", e)));
                             match eval_result {
                                 Ok(eval_result) => Ok(eval_result.to_string()),
-                                Err(e) => Err(format!("Python execution error: {}", e))
+                                Err(e) => Err(e)
                             }
                         },
                         None => Ok("None".to_string())
@@ -70,6 +87,102 @@ impl Runtime for PythonRuntime {
             }
         })
     }
+
+    fn eval_interactive(&self, input: &str) -> Result<Option<String>, String> {
+        // like the python repl: expressions are evaluated and their repr returned,
+        // anything else is executed as statements
+        Python::with_gil(|py| {
+            let globals = self.globals.bind(py);
+            let builtins = py.import("builtins").map_err(|e| format!("Python execution error: {}", e))?;
+
+            let result = match builtins.getattr("compile").and_then(|compile| compile.call1((input, "<repl>", "eval"))) {
+                Ok(code) => builtins.getattr("eval").and_then(|eval| eval.call1((code, globals))),
+                Err(_) => {
+                    let program = CString::new(input).map_err(|e| format!("Python execution error: {}", e))?;
+                    py.run(&program, Some(globals), None).map(|_| py.None().into_bound(py))
+                }
+            };
+
+            match result {
+                Ok(value) if value.is_none() => Ok(None),
+                Ok(value) => value.repr()
+                    .map(|repr| Some(repr.to_string()))
+                    .map_err(|e| format!("Python execution error: {}", e)),
+                Err(e) => Err(format!("Python execution error: {}", e)),
+            }
+        })
+    }
+
+    fn is_complete(&self, input: &str) -> bool {
+        // codeop.compile_command returns None when more input is required and raises
+        // on invalid input, which we report as complete so the error surfaces on eval
+        Python::with_gil(|py| {
+            py.import("codeop")
+                .and_then(|codeop| codeop.getattr("compile_command"))
+                .and_then(|compile_command| compile_command.call1((input, "<repl>", "single")))
+                .map(|code| !code.is_none())
+                .unwrap_or(true)
+        })
+    }
+
+    fn globals(&self) -> Vec<String> {
+        Python::with_gil(|py| {
+            self.globals
+                .bind(py)
+                .keys()
+                .iter()
+                .map(|key| key.to_string())
+                .filter(|key| !key.starts_with("__"))
+                .collect()
+        })
+    }
+}
+
+/// Dal has a reader and a printer but no evaluator yet, so this runtime reads
+/// each entry and prints back the data it reads, which shows how Dal sees it.
+#[derive(Default)]
+pub struct DalRuntime;
+
+impl DalRuntime {
+    pub fn new() -> Self {
+        DalRuntime
+    }
+
+    fn print(input: &str) -> Result<Vec<String>, String> {
+        Parser::new(input)
+            .map(|datum| datum.map(|datum| datum.to_string()))
+            .collect::<Result<_, DalError>>()
+            .map_err(|e| format!("Dal read error: {}", e))
+    }
+}
+
+impl Runtime for DalRuntime {
+    fn language(&self) -> Language {
+        Language::Dal
+    }
+
+    fn eval(&self, program: &str, expr: Option<&str>) -> Result<String, String> {
+        Self::print(program)?;
+        match expr {
+            Some(expr) => Ok(Self::print(expr)?.join(" ")),
+            None => Ok(String::new()),
+        }
+    }
+
+    fn eval_interactive(&self, input: &str) -> Result<Option<String>, String> {
+        let data = Self::print(input)?;
+        Ok((!data.is_empty()).then(|| data.join("\n")))
+    }
+
+    fn is_complete(&self, input: &str) -> bool {
+        // running out of input is the one error more lines can fix
+        let end = DalError::ParserError("unexpected end of input".to_string());
+        Parser::new(input).find_map(Result::err) != Some(end)
+    }
+
+    fn globals(&self) -> Vec<String> {
+        vec![]
+    }
 }
 
 #[cfg(test)]
@@ -95,4 +208,51 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Python execution error"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_python_runtime_eval_interactive() {
+        prepare_freethreaded_python();
+        let runtime = PythonRuntime::new();
+
+        assert_eq!(runtime.eval_interactive("x = 'abc'"), Ok(None));
+        assert_eq!(runtime.eval_interactive("x"), Ok(Some("'abc'".to_string())));
+        assert_eq!(runtime.eval_interactive("print(x)"), Ok(None));
+        assert!(runtime.eval_interactive("y").is_err());
+        assert_eq!(runtime.globals(), vec!["x".to_string()]);
+    }
+
+    #[test]
+    fn test_python_runtime_is_complete() {
+        prepare_freethreaded_python();
+        let runtime = PythonRuntime::new();
+
+        assert!(runtime.is_complete("x = 1"));
+        assert!(!runtime.is_complete("def f(x):"));
+        assert!(!runtime.is_complete("def f(x):\n    return x"));
+        assert!(runtime.is_complete("def f(x):\n    return x\n"));
+        assert!(runtime.is_complete("1 +* 2"));
+    }
+
+    #[test]
+    fn test_python_runtime_nul() {
+        prepare_freethreaded_python();
+        let runtime = PythonRuntime::new();
+
+        assert!(runtime.eval("x = 1\0", None).unwrap_err().contains("nul byte"));
+        assert!(runtime.eval("x = 1", Some("x\0")).unwrap_err().contains("nul byte"));
+    }
+
+    #[test]
+    fn test_dal_runtime() {
+        let runtime = DalRuntime::new();
+
+        assert_eq!(runtime.eval_interactive("(a . (b c)) 'x"), Ok(Some("(a b c)\n(quote x)".to_string())));
+        assert_eq!(runtime.eval_interactive("; nothing"), Ok(None));
+        assert!(runtime.eval_interactive(")").is_err());
+        assert_eq!(runtime.eval("(define x 1)", Some("x")), Ok("x".to_string()));
+
+        assert!(runtime.is_complete("(a b)"));
+        assert!(!runtime.is_complete("(a\n(b"));
+        assert!(runtime.is_complete(")"));
+    }
+}